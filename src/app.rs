@@ -0,0 +1,731 @@
+use crate::clients::auth_clients::restful::AuthClient;
+use crate::clients::dog_clients::restful::DogClient;
+use crate::clients::sms_verification_code_clients::restful::SMSVerificationCodeClient;
+use crate::clients::upload_clients::restful::UploadClient;
+use crate::clients::walk_request_clients::restful::WalkRequestClient;
+use crate::config::Config;
+use crate::core::guards::LoginGuardOptions;
+use crate::core::service::{
+    SMSLoginOptions, SMSSendOptions, Service, ServiceOptions,
+    WalkRequestOptions,
+};
+use crate::handlers::accounts::{
+    login_by_password, login_by_sms_verification_code, send_verification_code,
+};
+use crate::handlers::admin::{self, AdminState, RouteHandler, RouteInfo};
+use crate::handlers::common::pass_through;
+use crate::handlers::dogs::{update_dog, update_dog_portrait};
+use crate::handlers::error::route_not_found;
+use crate::handlers::health::{healthz, readyz};
+use crate::handlers::messages::Locale;
+use crate::handlers::metrics::metrics;
+use crate::handlers::uploads::{download, upload, UploadLimit};
+use crate::handlers::walk_requests::{
+    accept_walk_request, cancel_walk_request, create_walk_request,
+    finish_walk_request, nearby_walk_requests, start_walk_request,
+};
+use crate::middlewares::access_log::JsonAccessLogMiddlewareFactory;
+use crate::middlewares::auth::{AuthMiddlewareFactory, TokenCache};
+use crate::middlewares::cors::{CorsMiddlewareFactory, CorsPolicy};
+use crate::middlewares::error::ErrorEnvelopeMiddlewareFactory;
+use crate::middlewares::idempotency::IdempotencyMiddlewareFactory;
+use crate::middlewares::ip_filter::{IpFilterMiddlewareFactory, IpRule};
+use crate::middlewares::limits::{
+    BodyLimitRule, RequestLimitMiddlewareFactory, RequestLimits,
+};
+use crate::middlewares::metrics::MetricsMiddlewareFactory;
+use crate::middlewares::rate_limit::{
+    RateLimitKey, RateLimitMiddlewareFactory, RateLimitRule,
+};
+use crate::middlewares::request_id::RequestIdMiddlewareFactory;
+use crate::middlewares::security_headers::{
+    SecurityHeaders, SecurityHeadersMiddlewareFactory,
+};
+use crate::middlewares::tracing::TracingMiddlewareFactory;
+use crate::state::StateBackend;
+use crate::utils::health::{HealthCheckOptions, HealthChecker};
+use crate::utils::metrics::Metrics;
+use crate::utils::network::{IpBlocklist, TrustedProxies};
+use crate::utils::redact::Redactor;
+use crate::utils::telemetry::Telemetry;
+use crate::utils::upstream::Upstreams;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    http::Method,
+    middleware::{Condition, Logger},
+    web::{self, scope, Data, PayloadConfig},
+    App, Route, Scope,
+};
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Instantiates a generic handler with the restful clients used by the
+/// gateway.
+macro_rules! handler {
+    ($name:ident) => {
+        $name::<
+            AuthClient,
+            SMSVerificationCodeClient,
+            DogClient,
+            WalkRequestClient,
+            UploadClient,
+        >
+    };
+}
+
+/// Scopes whose requests are mostly passed through by a default service, they
+/// label requests matching no route in metrics and access logs.
+const PASS_THROUGH_SCOPES: &[&str] = &[
+    "/accounts",
+    "/apis/dogs",
+    "/apis/breeds",
+    "/apis/walk_requests",
+    "/apis/uploads",
+];
+
+/// The routes of `Gateway::app`, which registers them from here, as listed
+/// by the admin API.
+const ROUTES: &[RouteInfo] = &[
+    RouteInfo {
+        method: "GET",
+        path: "/metrics",
+        authenticated: false,
+        upstreams: &[],
+        handler: RouteHandler::Metrics,
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/healthz",
+        authenticated: false,
+        upstreams: &[],
+        handler: RouteHandler::Healthz,
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/readyz",
+        authenticated: false,
+        upstreams: &[
+            "auth",
+            "upload",
+            "sms_verification_code",
+            "dog",
+            "walk_request",
+        ],
+        handler: RouteHandler::Readyz,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/accounts/login/by_password",
+        authenticated: false,
+        upstreams: &["auth"],
+        handler: RouteHandler::LoginByPassword,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/accounts/login/by_sms_verification_code",
+        authenticated: false,
+        upstreams: &["sms_verification_code", "auth"],
+        handler: RouteHandler::LoginBySmsVerificationCode,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/accounts/phones/{phone}/verification_codes",
+        authenticated: false,
+        upstreams: &["sms_verification_code"],
+        handler: RouteHandler::SendVerificationCode,
+    },
+    RouteInfo {
+        method: "*",
+        path: "/accounts/*",
+        authenticated: false,
+        upstreams: &["auth"],
+        handler: RouteHandler::PassThrough,
+    },
+    RouteInfo {
+        method: "POST",
+        path: "/apis/dogs",
+        authenticated: true,
+        upstreams: &["dog"],
+        handler: RouteHandler::CreateDog,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/apis/dogs/{dog_id}",
+        authenticated: true,
+        upstreams: &["dog"],
+        handler: RouteHandler::UpdateDog,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/apis/dogs/{dog_id}/portrait",
+        authenticated: true,
+        upstreams: &["dog"],
+        handler: RouteHandler::UpdateDogPortrait,
+    },
+    RouteInfo {
+        method: "*",
+        path: "/apis/dogs/*",
+        authenticated: true,
+        upstreams: &["dog"],
+        handler: RouteHandler::PassThrough,
+    },
+    RouteInfo {
+        method: "*",
+        path: "/apis/breeds/*",
+        authenticated: true,
+        upstreams: &["dog"],
+        handler: RouteHandler::PassThrough,
+    },
+    RouteInfo {
+        method: "POST",
+        path: "/apis/walk_requests",
+        authenticated: true,
+        upstreams: &["walk_request"],
+        handler: RouteHandler::CreateWalkRequest,
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/apis/walk_requests/nearby",
+        authenticated: true,
+        upstreams: &["walk_request", "dog"],
+        handler: RouteHandler::NearbyWalkRequests,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/apis/walk_requests/{id}/accept",
+        authenticated: true,
+        upstreams: &["walk_request"],
+        handler: RouteHandler::AcceptWalkRequest,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/apis/walk_requests/{id}/cancel",
+        authenticated: true,
+        upstreams: &["walk_request"],
+        handler: RouteHandler::CancelWalkRequest,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/apis/walk_requests/{id}/start",
+        authenticated: true,
+        upstreams: &["walk_request"],
+        handler: RouteHandler::StartWalkRequest,
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/apis/walk_requests/{id}/finish",
+        authenticated: true,
+        upstreams: &["walk_request"],
+        handler: RouteHandler::FinishWalkRequest,
+    },
+    RouteInfo {
+        method: "*",
+        path: "/apis/walk_requests/*",
+        authenticated: true,
+        upstreams: &["walk_request"],
+        handler: RouteHandler::PassThrough,
+    },
+    RouteInfo {
+        method: "POST",
+        path: "/apis/uploads",
+        authenticated: true,
+        upstreams: &["upload"],
+        handler: RouteHandler::Upload,
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/apis/uploads/{id}",
+        authenticated: true,
+        upstreams: &["upload"],
+        handler: RouteHandler::Download,
+    },
+    RouteInfo {
+        method: "*",
+        path: "/apis/uploads/*",
+        authenticated: true,
+        upstreams: &["upload"],
+        handler: RouteHandler::PassThrough,
+    },
+];
+
+type RestfulService = Service<
+    AuthClient,
+    SMSVerificationCodeClient,
+    DogClient,
+    WalkRequestClient,
+    UploadClient,
+>;
+
+/// The text access logger, `%r` and `%U` carry tokens and phone numbers in
+/// paths and query strings so they are replaced by redacted counterparts.
+fn text_logger(format: &str, redactor: &Arc<Redactor>) -> Logger {
+    let format = format
+        .replace("%r", "%{redacted_request_line}xi")
+        .replace("%U", "%{redacted_path}xi");
+    let line_redactor = redactor.clone();
+    let path_redactor = redactor.clone();
+    Logger::new(&format)
+        .custom_request_replace("redacted_request_line", move |req| {
+            let line =
+                format!("{} {} {:?}", req.method(), req.uri(), req.version());
+            line_redactor.redact(&line).into_owned()
+        })
+        .custom_request_replace("redacted_path", move |req| {
+            path_redactor.redact(req.path()).into_owned()
+        })
+        .log_target("little-walk-api-gateway")
+}
+
+/// State shared by every worker, `app` builds the actix `App` serving the
+/// gateway routes on top of it.
+#[derive(Clone)]
+pub struct Gateway {
+    config: Config,
+    upstreams: Upstreams,
+    default_locale: Locale,
+    telemetry: Telemetry,
+    redactor: Arc<Redactor>,
+    metrics: Data<Metrics>,
+    health_checker: Data<HealthChecker>,
+    admin_state: Data<AdminState>,
+    service: Data<RestfulService>,
+    upload_limit: Data<UploadLimit>,
+    auth_middleware_factory: Arc<AuthMiddlewareFactory<AuthClient>>,
+    idempotency_middleware_factory: IdempotencyMiddlewareFactory,
+    rate_limit_middleware_factory: RateLimitMiddlewareFactory,
+    client_rate_limit_middleware_factory: RateLimitMiddlewareFactory,
+    cors_middleware_factory: CorsMiddlewareFactory,
+    security_headers_middleware_factory: SecurityHeadersMiddlewareFactory,
+    request_limit_middleware_factory: RequestLimitMiddlewareFactory,
+    payload_config: PayloadConfig,
+    trusted_proxies: Data<TrustedProxies>,
+    ip_filter_middleware_factory: IpFilterMiddlewareFactory,
+    admin_ip_filter_middleware_factory: IpFilterMiddlewareFactory,
+}
+
+impl Gateway {
+    pub fn new(config: Config) -> Self {
+        let metrics = Metrics::new();
+        let state = StateBackend::from_url(
+            &config.state_backend_url,
+            &config.state_key_prefix,
+            Duration::from_millis(config.state_backend_timeout_ms),
+        )
+        .expect("STATE_BACKEND_URL must be memory or a redis URL");
+        let upstreams = Upstreams::from_config(&config, &metrics);
+        let service = Data::new(Service::new(
+            AuthClient::new(upstreams.auth.clone()),
+            SMSVerificationCodeClient::new(
+                upstreams.sms_verification_code.clone(),
+            ),
+            DogClient::new(upstreams.dog.clone()),
+            WalkRequestClient::new(upstreams.walk_request.clone()),
+            UploadClient::new(upstreams.upload.clone()),
+            ServiceOptions {
+                sms_login: SMSLoginOptions {
+                    max_attempts: config.sms_login_max_attempts,
+                    attempt_window: Duration::from_secs(
+                        config.sms_login_attempt_window_secs,
+                    ),
+                    code_ttl: Duration::from_secs(config.sms_code_ttl_secs),
+                    auto_signup: config.sms_login_auto_signup,
+                },
+                sms_send: SMSSendOptions {
+                    phone_pattern: Regex::new(&config.sms_phone_pattern)
+                        .expect("SMS_PHONE_PATTERN must be a valid regex"),
+                    cooldown: Duration::from_secs(
+                        config.sms_send_cooldown_secs,
+                    ),
+                    daily_limit_per_phone: config
+                        .sms_send_daily_limit_per_phone,
+                    daily_limit_per_ip: config.sms_send_daily_limit_per_ip,
+                    test_phones: config
+                        .sms_test_phones
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|phone| !phone.is_empty())
+                        .map(str::to_owned)
+                        .collect(),
+                    test_code: config.sms_test_code.clone(),
+                },
+                login_guard: LoginGuardOptions {
+                    max_failures_per_phone: config.login_max_failures_per_phone,
+                    max_failures_per_ip: config.login_max_failures_per_ip,
+                    failure_window: Duration::from_secs(
+                        config.login_failure_window_secs,
+                    ),
+                    lockout: Duration::from_secs(config.login_lockout_secs),
+                    delay_after_failures: config.login_delay_after_failures,
+                    delay: Duration::from_millis(config.login_delay_ms),
+                    max_delay: Duration::from_millis(config.login_max_delay_ms),
+                },
+                walk_request: WalkRequestOptions {
+                    nearby_radius: config.nearby_default_radius,
+                },
+            },
+            state.clone(),
+        ));
+        let upload_limit = Data::new(UploadLimit(config.upload_size_limit));
+        let auth_middleware_factory = Arc::new(AuthMiddlewareFactory::new(
+            AuthClient::new(upstreams.auth.clone()),
+            TokenCache::new(
+                state.clone(),
+                Duration::from_secs(config.auth_cache_ttl_secs),
+            ),
+            metrics.clone(),
+        ));
+        let idempotency_middleware_factory = IdempotencyMiddlewareFactory::new(
+            state.clone(),
+            Duration::from_secs(config.idempotency_ttl_secs),
+        );
+        let (user_rate_limits, client_rate_limits) =
+            RateLimitRule::parse_all(&config.rate_limits)
+                .expect("RATE_LIMITS must be valid")
+                .into_iter()
+                .partition(|rule| rule.key == RateLimitKey::User);
+        let api_keys: HashSet<String> = config
+            .rate_limit_api_keys
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_owned)
+            .collect();
+        let rate_limit_middleware_factory = RateLimitMiddlewareFactory::new(
+            user_rate_limits,
+            api_keys.clone(),
+            state.clone(),
+            metrics.clone(),
+            PASS_THROUGH_SCOPES,
+        );
+        let client_rate_limit_middleware_factory =
+            RateLimitMiddlewareFactory::new(
+                client_rate_limits,
+                api_keys,
+                state.clone(),
+                metrics.clone(),
+                PASS_THROUGH_SCOPES,
+            );
+        let cors_middleware_factory = CorsMiddlewareFactory::new(
+            CorsPolicy::parse_all(&config.cors_policies)
+                .expect("CORS_POLICIES must be valid"),
+            PASS_THROUGH_SCOPES,
+        );
+        let security_headers_middleware_factory =
+            SecurityHeadersMiddlewareFactory::new(SecurityHeaders {
+                strict_transport_security: config
+                    .strict_transport_security
+                    .clone(),
+                x_content_type_options: config.x_content_type_options.clone(),
+                referrer_policy: config.referrer_policy.clone(),
+                content_security_policy: config.content_security_policy.clone(),
+            })
+            .expect("security headers must be valid header values");
+        let request_limits = RequestLimits {
+            max_url_length: config.max_url_length,
+            max_header_count: config.max_header_count,
+            max_header_bytes: config.max_header_bytes,
+            body_limits: BodyLimitRule::parse_all(&config.body_size_limits)
+                .expect("BODY_SIZE_LIMITS must be valid"),
+            upload_route: ROUTES
+                .iter()
+                .find(|route| matches!(route.handler, RouteHandler::Upload))
+                .map(|route| (route.method, route.path))
+                .expect("ROUTES must have an upload route"),
+        };
+        // Body limits are enforced by the middleware, the extractor must not
+        // be stricter than the largest of them.
+        let payload_config = match request_limits.largest_body_limit() {
+            Some(limit) => PayloadConfig::new(limit),
+            None => PayloadConfig::default(),
+        };
+        let request_limit_middleware_factory =
+            RequestLimitMiddlewareFactory::new(
+                request_limits,
+                PASS_THROUGH_SCOPES,
+            );
+        let trusted_proxies = TrustedProxies::parse(&config.trusted_proxies)
+            .expect("TRUSTED_PROXIES must be networks");
+        let ip_blocklist = IpBlocklist::new(state);
+        let ip_filter_middleware_factory = IpFilterMiddlewareFactory::new(
+            IpRule::parse_all(&config.ip_rules)
+                .expect("IP_RULES must be valid"),
+            Some(ip_blocklist.clone()),
+            PASS_THROUGH_SCOPES,
+        );
+        let admin_ip_filter_middleware_factory = IpFilterMiddlewareFactory::new(
+            IpRule::parse_all(&config.admin_ip_rules)
+                .expect("ADMIN_IP_RULES must be valid"),
+            None,
+            &[],
+        );
+        let default_locale = Locale::from_tag(&config.default_locale)
+            .expect("DEFAULT_LOCALE must be zh-CN or en");
+        let telemetry = Telemetry::from_config(&config);
+        let health_checker = HealthChecker::new(
+            &upstreams,
+            HealthCheckOptions {
+                critical: config
+                    .readiness_critical_upstreams
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+                cache_ttl: Duration::from_millis(config.readiness_cache_ttl_ms),
+                probe_timeout: Duration::from_millis(
+                    config.readiness_probe_timeout_ms,
+                ),
+            },
+            metrics.clone(),
+        )
+        .expect("READINESS_CRITICAL_UPSTREAMS must name upstreams");
+        let redactor = Arc::new(
+            Redactor::new(&config.redact_rules, &config.redact_patterns)
+                .expect("REDACT_RULES and REDACT_PATTERNS must be valid"),
+        );
+        let admin_state = Data::new(AdminState {
+            routes: ROUTES,
+            upstreams: upstreams.clone(),
+            config: config.masked(),
+            ip_blocklist,
+        });
+        Self {
+            config,
+            upstreams,
+            default_locale,
+            telemetry,
+            redactor,
+            metrics: Data::new(metrics),
+            health_checker: Data::new(health_checker),
+            admin_state,
+            service,
+            upload_limit,
+            auth_middleware_factory,
+            idempotency_middleware_factory,
+            rate_limit_middleware_factory,
+            client_rate_limit_middleware_factory,
+            cors_middleware_factory,
+            security_headers_middleware_factory,
+            request_limit_middleware_factory,
+            payload_config,
+            trusted_proxies: Data::new(trusted_proxies),
+            ip_filter_middleware_factory,
+            admin_ip_filter_middleware_factory,
+        }
+    }
+
+    /// Exports the spans finished so far, e.g. before the process exits.
+    pub fn flush_traces(&self) {
+        self.telemetry.force_flush();
+    }
+
+    /// The admin API, meant for a listener operators only can reach.
+    pub fn admin_app(
+        &self,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .wrap(self.admin_ip_filter_middleware_factory.clone())
+            .wrap(ErrorEnvelopeMiddlewareFactory::new(
+                self.config.sanitize_upstream_errors,
+                self.default_locale,
+                self.redactor.clone(),
+            ))
+            .wrap(RequestIdMiddlewareFactory)
+            .app_data(self.trusted_proxies.clone())
+            .app_data(self.admin_state.clone())
+            .app_data(self.health_checker.clone())
+            .default_service(web::to(route_not_found))
+            .route("/routes", web::get().to(admin::routes))
+            .route("/config", web::get().to(admin::config))
+            .route("/upstreams", web::get().to(admin::upstreams))
+            .route("/upstreams/{name}/drain", web::post().to(admin::drain))
+            .route("/upstreams/{name}/drain", web::delete().to(admin::undrain))
+            .route(
+                "/upstreams/{name}/circuit/open",
+                web::post().to(admin::open_circuit),
+            )
+            .route(
+                "/upstreams/{name}/circuit/reset",
+                web::post().to(admin::reset_circuit),
+            )
+            .route("/caches", web::get().to(admin::caches))
+            .route("/caches/flush", web::post().to(admin::flush_caches))
+            .route("/ip_blocks", web::get().to(admin::ip_blocks))
+            .route("/ip_blocks", web::post().to(admin::block_ip))
+            .route(
+                "/ip_blocks/{network:.+}",
+                web::delete().to(admin::unblock_ip),
+            )
+    }
+
+    pub fn app(
+        &self,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        let logger = text_logger(&self.config.log_format, &self.redactor);
+        let app = App::new()
+            .wrap(self.request_limit_middleware_factory.clone())
+            .wrap(self.ip_filter_middleware_factory.clone())
+            .wrap(ErrorEnvelopeMiddlewareFactory::new(
+                self.config.sanitize_upstream_errors,
+                self.default_locale,
+                self.redactor.clone(),
+            ))
+            .wrap(self.cors_middleware_factory.clone())
+            .wrap(self.security_headers_middleware_factory.clone())
+            .wrap(Condition::new(!self.config.log_json, logger))
+            .wrap(Condition::new(
+                self.config.log_json,
+                JsonAccessLogMiddlewareFactory::new(
+                    PASS_THROUGH_SCOPES,
+                    self.redactor.clone(),
+                ),
+            ))
+            .wrap(MetricsMiddlewareFactory::new(
+                self.metrics.get_ref().clone(),
+                PASS_THROUGH_SCOPES,
+            ))
+            .wrap(TracingMiddlewareFactory::new(
+                self.telemetry.tracer().clone(),
+                self.redactor.clone(),
+            ))
+            .wrap(RequestIdMiddlewareFactory)
+            .app_data(self.service.clone())
+            .app_data(self.payload_config.clone())
+            .app_data(self.trusted_proxies.clone())
+            .app_data(self.upload_limit.clone())
+            .app_data(self.metrics.clone())
+            .app_data(self.health_checker.clone())
+            .default_service(web::to(route_not_found));
+        ROUTES
+            .iter()
+            .filter(|route| scope_of(route).is_none())
+            .fold(app, |app, route| app.route(route.path, self.route(route)))
+            .service(
+                self.scope("/accounts")
+                    .wrap(self.rate_limit_middleware_factory.clone())
+                    .wrap(self.client_rate_limit_middleware_factory.clone()),
+            )
+            .service(
+                scope("apis")
+                    .wrap(self.idempotency_middleware_factory.clone())
+                    .wrap(self.rate_limit_middleware_factory.clone())
+                    .wrap(self.auth_middleware_factory.clone())
+                    .wrap(self.client_rate_limit_middleware_factory.clone())
+                    .service(self.scope("/apis/dogs"))
+                    .service(self.scope("/apis/breeds"))
+                    .service(self.scope("/apis/walk_requests"))
+                    .service(self.scope("/apis/uploads")),
+            )
+    }
+
+    /// A scope with the routes below `prefix`, `prefix/*` is its default
+    /// service.
+    fn scope(&self, prefix: &'static str) -> Scope {
+        let name = prefix.rsplit('/').next().unwrap_or_default();
+        ROUTES
+            .iter()
+            .filter(|route| scope_of(route) == Some(prefix))
+            .fold(scope(name), |scope, route| {
+                match &route.path[prefix.len()..] {
+                    "/*" => scope.default_service(self.route(route)),
+                    path => scope.route(path, self.route(route)),
+                }
+            })
+    }
+
+    fn route(&self, route: &RouteInfo) -> Route {
+        let builder = match route.method {
+            "*" => web::route(),
+            method => web::route().method(
+                Method::from_bytes(method.as_bytes())
+                    .expect("ROUTES must have valid methods"),
+            ),
+        };
+        let upstream = || {
+            route
+                .upstreams
+                .first()
+                .and_then(|name| self.upstreams.find(name))
+                .expect("ROUTES must pass through to a known upstream")
+        };
+        match route.handler {
+            RouteHandler::Metrics => builder.to(metrics),
+            RouteHandler::Healthz => builder.to(healthz),
+            RouteHandler::Readyz => builder.to(readyz),
+            RouteHandler::LoginByPassword => {
+                builder.to(handler!(login_by_password))
+            }
+            RouteHandler::LoginBySmsVerificationCode => {
+                builder.to(handler!(login_by_sms_verification_code))
+            }
+            RouteHandler::SendVerificationCode => {
+                builder.to(handler!(send_verification_code))
+            }
+            RouteHandler::PassThrough => builder.to(pass_through(
+                upstream(),
+                None,
+                self.service.no_op_request_body_processor(),
+                self.service.no_op_processor(),
+            )),
+            RouteHandler::CreateDog => builder.to(pass_through(
+                upstream(),
+                None,
+                self.service.create_dog_request_body_processor(),
+                self.service.no_op_processor(),
+            )),
+            RouteHandler::UpdateDog => builder.to(handler!(update_dog)),
+            RouteHandler::UpdateDogPortrait => {
+                builder.to(handler!(update_dog_portrait))
+            }
+            RouteHandler::CreateWalkRequest => {
+                builder.to(handler!(create_walk_request))
+            }
+            RouteHandler::NearbyWalkRequests => {
+                builder.to(handler!(nearby_walk_requests))
+            }
+            RouteHandler::AcceptWalkRequest => {
+                builder.to(handler!(accept_walk_request))
+            }
+            RouteHandler::CancelWalkRequest => {
+                builder.to(handler!(cancel_walk_request))
+            }
+            RouteHandler::StartWalkRequest => {
+                builder.to(handler!(start_walk_request))
+            }
+            RouteHandler::FinishWalkRequest => {
+                builder.to(handler!(finish_walk_request))
+            }
+            RouteHandler::Upload => builder.to(handler!(upload)),
+            RouteHandler::Download => builder.to(handler!(download)),
+        }
+    }
+}
+
+/// The pass through scope `route` is registered in, if any.
+fn scope_of(route: &RouteInfo) -> Option<&'static str> {
+    PASS_THROUGH_SCOPES.iter().copied().find(|scope| {
+        route
+            .path
+            .strip_prefix(scope)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
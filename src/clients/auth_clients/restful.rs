@@ -2,24 +2,22 @@ use crate::core::error::Error;
 use crate::core::service::ByteStream;
 use crate::utils::io::stream_to_bytes;
 use crate::utils::restful::{make_request, parse_url, RequestBody};
+use crate::utils::upstream::Upstream;
 use crate::{
     core::clients::auth::AuthClient as IAuthClient, utils::restful::request,
 };
-use http::StatusCode;
 use reqwest::{Client, Method};
 use serde::{Deserialize, Serialize};
 use serde_json::from_slice;
 
 #[derive(Clone)]
 pub struct AuthClient {
-    host_and_port: String,
+    upstream: Upstream,
 }
 
 impl AuthClient {
-    pub fn new(host_and_port: &str) -> Self {
-        Self {
-            host_and_port: host_and_port.to_string(),
-        }
+    pub fn new(upstream: Upstream) -> Self {
+        Self { upstream }
     }
 }
 
@@ -37,6 +35,7 @@ pub struct LoginReq {
 #[derive(Debug, Serialize)]
 pub struct SignupReq {
     pub phone: String,
+    pub verification_code: String,
     pub password: String,
 }
 
@@ -49,7 +48,7 @@ impl IAuthClient for AuthClient {
     async fn exists_user(&self, phone: &str) -> Result<bool, Error> {
         let body = make_request(
             Method::GET,
-            &self.host_and_port,
+            &self.upstream,
             &format!("/phones/{}/exists", phone),
             None,
             Option::<()>::None,
@@ -57,16 +56,15 @@ impl IAuthClient for AuthClient {
         )
         .await?;
         let bs = stream_to_bytes(body).await?;
-        let result: ExistsUserResp = from_slice(&bs).map_err(|e| {
-            Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-        })?;
+        let result: ExistsUserResp =
+            from_slice(&bs).map_err(self.upstream.invalid_response())?;
         Ok(result.exists)
     }
 
     async fn generate_token(&self, phone: &str) -> Result<ByteStream, Error> {
         make_request(
             Method::PUT,
-            &self.host_and_port,
+            &self.upstream,
             &format!("/phones/{}/tokens", phone),
             None,
             Option::<()>::None,
@@ -82,8 +80,8 @@ impl IAuthClient for AuthClient {
     ) -> Result<ByteStream, Error> {
         make_request(
             Method::PUT,
-            &self.host_and_port,
-            "/login",
+            &self.upstream,
+            "/accounts/login/by_password",
             None,
             Option::<()>::None,
             RequestBody::Json(LoginReq {
@@ -97,16 +95,18 @@ impl IAuthClient for AuthClient {
     async fn signup(
         &self,
         phone: &str,
+        verification_code: &str,
         password: &str,
     ) -> Result<ByteStream, Error> {
         make_request(
             Method::POST,
-            &self.host_and_port,
-            "/signup",
+            &self.upstream,
+            "/accounts/signup",
             None,
             Option::<()>::None,
             RequestBody::Json(SignupReq {
                 phone: phone.into(),
+                verification_code: verification_code.into(),
                 password: password.into(),
             }),
         )
@@ -115,17 +115,15 @@ impl IAuthClient for AuthClient {
 
     async fn verify_token(&self, token: &str) -> Result<String, Error> {
         let url = parse_url(
-            &self.host_and_port,
+            &self.upstream.address,
             &format!("/tokens/{}/verification", token),
             None,
         )?;
         let builder = Client::new().request(Method::GET, url);
-        let stream = request(builder).await?;
+        let stream = request(&self.upstream, builder).await?;
         let bs = stream_to_bytes(stream).await?;
-        let result: VerifyTokenResp =
-            serde_json::from_slice(&bs).map_err(|e| {
-                Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-            })?;
+        let result: VerifyTokenResp = serde_json::from_slice(&bs)
+            .map_err(self.upstream.invalid_response())?;
         Ok(result.id)
     }
 }
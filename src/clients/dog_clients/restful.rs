@@ -0,0 +1,144 @@
+use crate::core::clients::dog::{Dog, DogClient as IDogClient};
+use crate::core::error::Error;
+use crate::core::requests::{DogCreateIncome, DogPortraitUpdate, DogQuery};
+use crate::core::service::ByteStream;
+use crate::utils::io::stream_to_bytes;
+use crate::utils::restful::{make_request, RequestBody};
+use crate::utils::upstream::Upstream;
+use bytes::Bytes;
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::{from_slice, Value};
+
+#[derive(Clone)]
+pub struct DogClient {
+    upstream: Upstream,
+}
+
+impl DogClient {
+    pub fn new(upstream: Upstream) -> Self {
+        Self { upstream }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OwnerQuery<'a> {
+    pub owner_id: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BreedCategoryQuery<'a> {
+    pub category: &'a str,
+}
+
+impl IDogClient for DogClient {
+    async fn add_dog(
+        &self,
+        owner_id: &str,
+        body: ByteStream,
+    ) -> Result<ByteStream, Error> {
+        let bs = stream_to_bytes(body).await?;
+        let income: DogCreateIncome =
+            from_slice(&bs).map_err(Error::invalid_body)?;
+        make_request(
+            Method::POST,
+            &self.upstream,
+            "/apis/dogs",
+            None,
+            Option::<()>::None,
+            RequestBody::Json(income.into_create(owner_id.to_owned())),
+        )
+        .await
+    }
+
+    async fn dogs_by_owner_id(
+        &self,
+        owner_id: &str,
+    ) -> Result<ByteStream, Error> {
+        make_request(
+            Method::GET,
+            &self.upstream,
+            "/apis/dogs",
+            None,
+            Some(OwnerQuery { owner_id }),
+            RequestBody::<()>::None,
+        )
+        .await
+    }
+
+    async fn query_dogs(&self, query: &DogQuery) -> Result<Vec<Dog>, Error> {
+        let body = make_request(
+            Method::GET,
+            &self.upstream,
+            "/apis/dogs",
+            None,
+            Some(query),
+            RequestBody::<()>::None,
+        )
+        .await?;
+        let bs = stream_to_bytes(body).await?;
+        from_slice(&bs).map_err(self.upstream.invalid_response())
+    }
+
+    async fn is_owner_of_the_dog(
+        &self,
+        owner_id: &str,
+        dog_id: &str,
+    ) -> Result<bool, Error> {
+        let dogs = self
+            .query_dogs(&DogQuery {
+                id: Some(dog_id.to_owned()),
+                owner_id: Some(owner_id.to_owned()),
+                ..Default::default()
+            })
+            .await?;
+        Ok(!dogs.is_empty())
+    }
+
+    async fn update_dog_portrait(
+        &self,
+        dog_id: &str,
+        portrait_id: &str,
+    ) -> Result<ByteStream, Error> {
+        make_request(
+            Method::PUT,
+            &self.upstream,
+            &format!("/apis/dogs/{}/portrait", dog_id),
+            None,
+            Option::<()>::None,
+            RequestBody::Json(DogPortraitUpdate {
+                portrait_id: portrait_id.to_owned(),
+            }),
+        )
+        .await
+    }
+
+    async fn query_breeds(&self, category: &str) -> Result<ByteStream, Error> {
+        make_request(
+            Method::GET,
+            &self.upstream,
+            "/apis/breeds",
+            None,
+            Some(BreedCategoryQuery { category }),
+            RequestBody::<()>::None,
+        )
+        .await
+    }
+
+    async fn update_dog(
+        &self,
+        dog_id: &str,
+        body: Bytes,
+    ) -> Result<ByteStream, Error> {
+        let update: Value = from_slice(&body).map_err(Error::invalid_body)?;
+        make_request(
+            Method::PUT,
+            &self.upstream,
+            &format!("/apis/dogs/{}", dog_id),
+            None,
+            Option::<()>::None,
+            RequestBody::Json(update),
+        )
+        .await
+    }
+}
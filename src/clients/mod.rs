@@ -1 +1,5 @@
 pub mod auth_clients;
+pub mod dog_clients;
+pub mod sms_verification_code_clients;
+pub mod upload_clients;
+pub mod walk_request_clients;
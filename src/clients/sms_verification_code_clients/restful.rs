@@ -0,0 +1,62 @@
+use crate::core::clients::sms_verification_code::SMSVerificationCodeClient as ISMSVerificationCodeClient;
+use crate::core::error::Error;
+use crate::core::service::ByteStream;
+use crate::utils::io::stream_to_bytes;
+use crate::utils::restful::{make_request, RequestBody};
+use crate::utils::upstream::Upstream;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::from_slice;
+
+#[derive(Clone)]
+pub struct SMSVerificationCodeClient {
+    upstream: Upstream,
+}
+
+impl SMSVerificationCodeClient {
+    pub fn new(upstream: Upstream) -> Self {
+        Self { upstream }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyCodeResp {
+    is_valid: bool,
+}
+
+impl ISMSVerificationCodeClient for SMSVerificationCodeClient {
+    async fn send_code(&self, phone: &str) -> Result<ByteStream, Error> {
+        make_request(
+            Method::PUT,
+            &self.upstream,
+            &format!("/phones/{}/verification_codes", phone),
+            None,
+            Option::<()>::None,
+            RequestBody::<()>::None,
+        )
+        .await
+    }
+
+    async fn verify_code(
+        &self,
+        phone: &str,
+        code: &str,
+    ) -> Result<bool, Error> {
+        let body = make_request(
+            Method::GET,
+            &self.upstream,
+            &format!(
+                "/phones/{}/verification_codes/{}/verification",
+                phone, code
+            ),
+            None,
+            Option::<()>::None,
+            RequestBody::<()>::None,
+        )
+        .await?;
+        let bs = stream_to_bytes(body).await?;
+        let result: VerifyCodeResp =
+            from_slice(&bs).map_err(self.upstream.invalid_response())?;
+        Ok(result.is_valid)
+    }
+}
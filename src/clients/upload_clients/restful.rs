@@ -0,0 +1,75 @@
+use crate::core::clients::upload::{Download, UploadClient as IUploadClient};
+use crate::core::error::{Error, ErrorKind};
+use crate::core::service::ByteStream;
+use crate::utils::io::limit_stream;
+use crate::utils::restful::{parse_url, response_to_stream, send, send_with};
+use crate::utils::upstream::Upstream;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Body, Client, Method};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct UploadClient {
+    upstream: Upstream,
+}
+
+impl UploadClient {
+    pub fn new(upstream: Upstream) -> Self {
+        Self { upstream }
+    }
+}
+
+impl IUploadClient for UploadClient {
+    async fn upload(
+        &self,
+        content_type_header: &str,
+        user_id: &str,
+        limit: usize,
+        payload: ByteStream,
+    ) -> Result<ByteStream, Error> {
+        let url = parse_url(&self.upstream.address, "/apis/uploads", None)?;
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let body = limit_stream(payload, limit, exceeded.clone());
+        let builder = Client::new()
+            .request(Method::POST, url)
+            .header(CONTENT_TYPE, content_type_header)
+            .header("X-User-ID", user_id)
+            .body(Body::wrap_stream(body));
+        let resp = send_with(&self.upstream, builder, |e| {
+            if !exceeded.load(Ordering::SeqCst) {
+                return e;
+            }
+            Error::new(
+                ErrorKind::PayloadTooLarge,
+                format!("payload exceeds the limit of {} bytes", limit),
+            )
+            .with_details(json!({ "limit": limit }))
+        })
+        .await?;
+        Ok(response_to_stream(&self.upstream, resp))
+    }
+
+    async fn download(&self, id: &str) -> Result<Download, Error> {
+        let url = parse_url(
+            &self.upstream.address,
+            &format!("/apis/uploads/{}", id),
+            None,
+        )?;
+        let resp =
+            send(&self.upstream, Client::new().request(Method::GET, url))
+                .await?;
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned());
+        let content_length = resp.content_length();
+        Ok(Download {
+            content_type,
+            content_length,
+            body: response_to_stream(&self.upstream, resp),
+        })
+    }
+}
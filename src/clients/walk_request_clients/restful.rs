@@ -0,0 +1,255 @@
+use crate::core::clients::walk_request::{
+    WalkRequest, WalkRequestClient as IWalkRequestClient, WalkRequestCreate,
+    WalkRequestQuery,
+};
+use crate::core::error::{Error, ErrorKind};
+use crate::core::service::ByteStream;
+use crate::utils::io::stream_to_bytes;
+use crate::utils::restful::{make_request, parse_url, request, RequestBody};
+use crate::utils::upstream::Upstream;
+use nb_serde_query::Array;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Client, Method};
+use serde_json::from_slice;
+use url::form_urlencoded::Serializer;
+
+#[derive(Clone)]
+pub struct WalkRequestClient {
+    upstream: Upstream,
+}
+
+impl WalkRequestClient {
+    pub fn new(upstream: Upstream) -> Self {
+        Self { upstream }
+    }
+
+    async fn transit(
+        &self,
+        user_id: &str,
+        id: &str,
+        action: &str,
+    ) -> Result<ByteStream, Error> {
+        make_request(
+            Method::PUT,
+            &self.upstream,
+            &format!("/apis/walk_requests/{}/{}", id, action),
+            Some(user_id_headers(user_id)?),
+            Option::<()>::None,
+            RequestBody::<()>::None,
+        )
+        .await
+    }
+}
+
+fn user_id_headers(user_id: &str) -> Result<HeaderMap, Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "X-User-ID",
+        HeaderValue::from_str(user_id)
+            .map_err(Error::wrap(ErrorKind::Internal))?,
+    );
+    Ok(headers)
+}
+
+fn append_array(
+    serializer: &mut Serializer<String>,
+    key: &str,
+    value: &Option<Array<String>>,
+) -> Result<(), Error> {
+    if let Some(value) = value {
+        let json = serde_json::to_string(&value.0)
+            .map_err(Error::wrap(ErrorKind::Internal))?;
+        serializer.append_pair(key, &json);
+    }
+    Ok(())
+}
+
+/// Encodes the query the way the walk request service decodes it: `nearby`
+/// and `pagination` are flattened into top level keys, arrays are JSON
+/// encoded and `accepted_by_is_null` is always present.
+pub fn encode_query(query: &WalkRequestQuery) -> Result<String, Error> {
+    let mut serializer = Serializer::new(String::new());
+    if let Some(id) = &query.id {
+        serializer.append_pair("id", id);
+    }
+    append_array(
+        &mut serializer,
+        "dog_ids_includes_all",
+        &query.dog_ids_includes_all,
+    )?;
+    append_array(
+        &mut serializer,
+        "dog_ids_includes_any",
+        &query.dog_ids_includes_any,
+    )?;
+    if let Some(nearby) = &query.nearby {
+        serializer.append_pair("latitude", &nearby.latitude.to_string());
+        serializer.append_pair("longitude", &nearby.longitude.to_string());
+        serializer.append_pair("radius", &nearby.radius.to_string());
+    }
+    if let Some(accepted_by) = &query.accepted_by {
+        serializer.append_pair("accepted_by", accepted_by);
+    }
+    if let Some(accepted_by_neq) = &query.accepted_by_neq {
+        serializer.append_pair("accepted_by_neq", accepted_by_neq);
+    }
+    serializer.append_pair(
+        "accepted_by_is_null",
+        &query.accepted_by_is_null.to_string(),
+    );
+    append_array(
+        &mut serializer,
+        "acceptances_includes_all",
+        &query.acceptances_includes_all,
+    )?;
+    append_array(
+        &mut serializer,
+        "acceptances_includes_any",
+        &query.acceptances_includes_any,
+    )?;
+    if let Some(pagination) = &query.pagination {
+        serializer.append_pair("page", &pagination.page.to_string());
+        serializer.append_pair("size", &pagination.size.to_string());
+    }
+    Ok(serializer.finish())
+}
+
+impl IWalkRequestClient for WalkRequestClient {
+    async fn query_walk_requests(
+        &self,
+        query: WalkRequestQuery,
+    ) -> Result<Vec<WalkRequest>, Error> {
+        let url = parse_url(
+            &self.upstream.address,
+            "/apis/walk_requests",
+            Some(&encode_query(&query)?),
+        )?;
+        let builder = Client::new().request(Method::GET, url);
+        let stream = request(&self.upstream, builder).await?;
+        let bs = stream_to_bytes(stream).await?;
+        from_slice(&bs).map_err(self.upstream.invalid_response())
+    }
+
+    async fn create_walk_request(
+        &self,
+        user_id: &str,
+        create: &WalkRequestCreate,
+    ) -> Result<ByteStream, Error> {
+        make_request(
+            Method::POST,
+            &self.upstream,
+            "/apis/walk_requests",
+            Some(user_id_headers(user_id)?),
+            Option::<()>::None,
+            RequestBody::Json(create),
+        )
+        .await
+    }
+
+    async fn accept_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.transit(user_id, id, "accept").await
+    }
+
+    async fn cancel_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.transit(user_id, id, "cancel").await
+    }
+
+    async fn start_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.transit(user_id, id, "start").await
+    }
+
+    async fn finish_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.transit(user_id, id, "finish").await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::clients::walk_request::Nearby;
+    use crate::core::common::Pagination;
+    use crate::utils::restful::from_query;
+
+    #[test]
+    fn encode_empty_query() {
+        let query = WalkRequestQuery::default();
+        assert_eq!(encode_query(&query).unwrap(), "accepted_by_is_null=false");
+    }
+
+    #[test]
+    fn encode_nearby_query() {
+        let query = WalkRequestQuery {
+            nearby: Some(Nearby {
+                latitude: 36.5,
+                longitude: 117.25,
+                radius: 1000.0,
+            }),
+            pagination: Some(Pagination { page: 1, size: 20 }),
+            ..Default::default()
+        };
+        assert_eq!(
+            encode_query(&query).unwrap(),
+            "latitude=36.5&longitude=117.25&radius=1000\
+             &accepted_by_is_null=false&page=1&size=20"
+        );
+    }
+
+    #[test]
+    fn encode_arrays_as_json() {
+        let query = WalkRequestQuery {
+            acceptances_includes_any: Some(Array(vec![
+                "a b".to_owned(),
+                "c&d".to_owned(),
+            ])),
+            ..Default::default()
+        };
+        assert_eq!(
+            encode_query(&query).unwrap(),
+            "accepted_by_is_null=false\
+             &acceptances_includes_any=%5B%22a+b%22%2C%22c%26d%22%5D"
+        );
+    }
+
+    #[test]
+    fn round_trip_every_field() {
+        let query = WalkRequestQuery {
+            id: Some("6546ed8fb5093c4041c22b88".to_owned()),
+            dog_ids_includes_all: Some(Array(vec!["1".into(), "2".into()])),
+            dog_ids_includes_any: Some(Array(vec!["3".into()])),
+            nearby: Some(Nearby {
+                latitude: 36.651,
+                longitude: 117.12,
+                radius: 500.5,
+            }),
+            accepted_by: Some("u1".to_owned()),
+            accepted_by_neq: Some("u2".to_owned()),
+            accepted_by_is_null: true,
+            acceptances_includes_all: Some(Array(vec!["u3".into()])),
+            acceptances_includes_any: Some(Array(vec![
+                "u4".into(),
+                "u5".into(),
+            ])),
+            pagination: Some(Pagination { page: 3, size: 15 }),
+        };
+        let encoded = encode_query(&query).unwrap();
+        let decoded = from_query::<WalkRequestQuery>(&encoded).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", query));
+        assert_eq!(encode_query(&decoded).unwrap(), encoded);
+    }
+}
@@ -0,0 +1,203 @@
+use nb_from_env::{FromEnv, FromEnvDerive};
+use serde::Serialize;
+use serde_json::Value;
+use url::Url;
+
+/// Fields whose name contains one of these hold secrets.
+const SECRET_FIELDS: &[&str] =
+    &["password", "secret", "token", "key", "test_code"];
+
+#[derive(FromEnvDerive, Clone, Serialize)]
+pub struct Config {
+    pub listen_address: String,
+    // The admin API listener, keep it unreachable from outside.
+    #[env_default("127.0.0.1:9001")]
+    pub admin_listen_address: String,
+    #[env_default("info")]
+    pub log_level: String,
+    #[env_default("%t %{X-Request-ID}i %s %r %a %D")]
+    pub log_format: String,
+    // Write access logs and every other log line as JSON, log_format is
+    // ignored then.
+    #[env_default("false")]
+    pub log_json: bool,
+    // Built-in rules masking secrets in logs and traces, any of tokens,
+    // phones, codes and passwords.
+    #[env_default("tokens,phones,codes,passwords")]
+    pub redact_rules: String,
+    // Extra whitespace separated regular expressions masked as well.
+    #[env_default("")]
+    pub redact_patterns: String,
+    pub auth_service_address: String,
+    pub upload_service_address: String,
+    pub sms_verification_code_service_address: String,
+    pub dog_service_address: String,
+    pub walk_request_service_address: String,
+    #[env_default("10485760")]
+    pub upload_size_limit: usize,
+    #[env_default("5")]
+    pub sms_login_max_attempts: u32,
+    #[env_default("600")]
+    pub sms_login_attempt_window_secs: u64,
+    // How long sms codes stay valid, a used one is refused for as long.
+    #[env_default("600")]
+    pub sms_code_ttl_secs: u64,
+    #[env_default("false")]
+    pub sms_login_auto_signup: bool,
+    #[env_default("^1[3-9][0-9]{9}$")]
+    pub sms_phone_pattern: String,
+    #[env_default("60")]
+    pub sms_send_cooldown_secs: u64,
+    #[env_default("10")]
+    pub sms_send_daily_limit_per_phone: u32,
+    #[env_default("50")]
+    pub sms_send_daily_limit_per_ip: u32,
+    // Comma separated phones that are never sent a code, SMS_TEST_CODE is
+    // valid for them. Keep empty in production.
+    #[env_default("")]
+    pub sms_test_phones: String,
+    #[env_default("123456")]
+    pub sms_test_code: String,
+    // Failed password and SMS code logins within the window lock the phone
+    // or IP for LOGIN_LOCKOUT_SECS. Failures beyond the first
+    // LOGIN_DELAY_AFTER_FAILURES are answered after a delay doubling from
+    // LOGIN_DELAY_MS up to LOGIN_MAX_DELAY_MS.
+    #[env_default("5")]
+    pub login_max_failures_per_phone: u32,
+    #[env_default("20")]
+    pub login_max_failures_per_ip: u32,
+    #[env_default("900")]
+    pub login_failure_window_secs: u64,
+    #[env_default("900")]
+    pub login_lockout_secs: u64,
+    #[env_default("2")]
+    pub login_delay_after_failures: u32,
+    #[env_default("500")]
+    pub login_delay_ms: u64,
+    #[env_default("4000")]
+    pub login_max_delay_ms: u64,
+    // Radius in meters of nearby walk request queries that give none.
+    #[env_default("3000")]
+    pub nearby_default_radius: f64,
+    // Replace upstream error bodies that do not follow the error schema
+    // with a generic message, disable only for local debugging.
+    #[env_default("true")]
+    pub sanitize_upstream_errors: bool,
+    // Locale of error messages when Accept-Language names none we support,
+    // either zh-CN or en.
+    #[env_default("zh-CN")]
+    pub default_locale: String,
+    // Base URL of an OTLP/HTTP collector, e.g. http://localhost:4318, spans
+    // are not exported when empty.
+    #[env_default("")]
+    pub otlp_endpoint: String,
+    #[env_default("little-walk-api-gateway")]
+    pub otlp_service_name: String,
+    // Consecutive upstream failures failing further calls fast for a while,
+    // 0 disables the circuit breaker.
+    #[env_default("0")]
+    pub circuit_breaker_failure_threshold: u32,
+    #[env_default("30")]
+    pub circuit_breaker_open_secs: u64,
+    // Upstreams /readyz requires to be reachable, the others are reported
+    // only.
+    #[env_default("auth,upload,sms_verification_code,dog,walk_request")]
+    pub readiness_critical_upstreams: String,
+    #[env_default("5000")]
+    pub readiness_cache_ttl_ms: u64,
+    #[env_default("1000")]
+    pub readiness_probe_timeout_ms: u64,
+    // Comma separated <route>=<requests>/<seconds>[:user|ip|api_key], the
+    // route is a pattern such as /apis/dogs/{dog_id}, /accounts/* or *.
+    // User keyed rules apply once auth passed, the others before it.
+    #[env_default(
+        "/apis/walk_requests/nearby=60/60:user,*=600/60:user,*=1200/60:ip"
+    )]
+    pub rate_limits: String,
+    // Comma separated X-API-Key values that get buckets of their own.
+    #[env_default("")]
+    pub rate_limit_api_keys: String,
+    // memory, or a redis:// URL to share state between replicas.
+    #[env_default("memory")]
+    pub state_backend_url: String,
+    #[env_default("little-walk-api-gateway:")]
+    pub state_key_prefix: String,
+    #[env_default("500")]
+    pub state_backend_timeout_ms: u64,
+    // Seconds a verified auth token is trusted without asking auth again, 0
+    // to ask on every request. Tokens revoked at auth keep working as long.
+    #[env_default("60")]
+    pub auth_cache_ttl_secs: u64,
+    // Seconds the response to a request with an Idempotency-Key header is
+    // replayed for retries of it.
+    #[env_default("86400")]
+    pub idempotency_ttl_secs: u64,
+    // A JSON list of CORS policies, the first one whose routes match applies,
+    // e.g. [{"routes": ["/apis/*"], "allowed_origins":
+    // ["https://*.example.com"], "allow_credentials": true}].
+    #[env_default("[]")]
+    pub cors_policies: String,
+    // Security headers added to responses, empty ones are left out. The
+    // content security policy is sent along with HTML pages only.
+    #[env_default("max-age=31536000; includeSubDomains")]
+    pub strict_transport_security: String,
+    #[env_default("nosniff")]
+    pub x_content_type_options: String,
+    #[env_default("no-referrer")]
+    pub referrer_policy: String,
+    #[env_default("default-src 'self'; frame-ancestors 'none'")]
+    pub content_security_policy: String,
+    // Requests beyond these are rejected before being forwarded, header
+    // bytes count names and values.
+    #[env_default("4096")]
+    pub max_url_length: usize,
+    #[env_default("64")]
+    pub max_header_count: usize,
+    #[env_default("16384")]
+    pub max_header_bytes: usize,
+    // Comma separated <route>=<bytes>, * applies to routes without a limit
+    // of their own. Multipart uploads are bounded by UPLOAD_SIZE_LIMIT.
+    #[env_default("*=65536")]
+    pub body_size_limits: String,
+    // Comma separated networks of the proxies in front of the gateway, the
+    // client IP is taken from X-Forwarded-For only if they sent it.
+    #[env_default("")]
+    pub trusted_proxies: String,
+    // A JSON list of IP rules, every rule whose routes match must admit the
+    // client, e.g. [{"routes": ["/metrics"], "allow": ["10.8.0.0/16"]},
+    // {"routes": ["*"], "deny": ["203.0.113.7"]}]. By default only loopback
+    // clients may read the metrics.
+    #[env_default(
+        r#"[{"routes": ["/metrics"], "allow": ["127.0.0.0/8", "::1"]}]"#
+    )]
+    pub ip_rules: String,
+    // IP rules of the admin API in the format of IP_RULES, e.g.
+    // [{"routes": ["*"], "allow": ["10.8.0.0/16"]}]. Runtime blocks don't
+    // apply to it, so operators can't lock themselves out.
+    #[env_default("[]")]
+    pub admin_ip_rules: String,
+}
+
+impl Config {
+    /// The effective config for inspection, secret fields and credentials in
+    /// URLs are masked.
+    pub fn masked(&self) -> Value {
+        let mut config = serde_json::to_value(self).unwrap();
+        for (name, value) in config.as_object_mut().unwrap().iter_mut() {
+            if SECRET_FIELDS.iter().any(|secret| name.contains(secret)) {
+                *value = Value::from("***");
+                continue;
+            }
+            let Some(mut url) = value.as_str().and_then(|v| Url::parse(v).ok())
+            else {
+                continue;
+            };
+            if !url.username().is_empty() || url.password().is_some() {
+                let _ = url.set_username("***");
+                let _ = url.set_password(None);
+                *value = Value::from(url.to_string());
+            }
+        }
+        config
+    }
+}
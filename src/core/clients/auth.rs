@@ -6,6 +6,7 @@ pub trait AuthClient: Clone + 'static {
     async fn signup(
         &self,
         phone: &str,
+        verification_code: &str,
         password: &str,
     ) -> Result<ByteStream, Error>;
     async fn login(
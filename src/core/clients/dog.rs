@@ -6,7 +6,7 @@ use chrono::{DateTime, Utc};
 use crate::core::{requests::DogQuery, service::ByteStream};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Breed {
     pub id: String,
     pub category: String,
@@ -15,7 +15,7 @@ pub struct Breed {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Dog {
     pub id: String,
     pub name: String,
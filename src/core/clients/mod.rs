@@ -1,3 +1,7 @@
+// The clients are only awaited on actix's single threaded workers, so their
+// futures need not be Send.
+#![allow(async_fn_in_trait)]
+
 pub mod auth;
 pub mod dog;
 pub mod sms_verification_code;
@@ -2,6 +2,12 @@ use crate::core::error::Error;
 
 use crate::core::service::ByteStream;
 
+pub struct Download {
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    pub body: ByteStream,
+}
+
 pub trait UploadClient: Clone + 'static {
     async fn upload(
         &self,
@@ -11,5 +17,5 @@ pub trait UploadClient: Clone + 'static {
         payload: ByteStream,
     ) -> Result<ByteStream, Error>;
 
-    async fn download(&self, id: &str) -> Result<ByteStream, Error>;
+    async fn download(&self, id: &str) -> Result<Download, Error>;
 }
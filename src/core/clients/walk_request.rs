@@ -4,8 +4,9 @@ use nb_serde_query::Array;
 use serde::{Deserialize, Serialize};
 
 use crate::core::common::Pagination;
+use crate::core::service::ByteStream;
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct WalkRequest {
     pub id: String,
     pub dog_ids: Vec<String>,
@@ -29,25 +30,110 @@ pub struct Nearby {
     pub radius: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// Sent as encoded by `encode_query` of the restful client.
+#[derive(Debug, Deserialize, Default)]
+#[serde(from = "FlatWalkRequestQuery")]
 pub struct WalkRequestQuery {
     pub id: Option<String>,
     pub dog_ids_includes_all: Option<Array<String>>,
     pub dog_ids_includes_any: Option<Array<String>>,
-    #[serde(flatten)]
     pub nearby: Option<Nearby>,
     pub accepted_by: Option<String>,
     pub accepted_by_neq: Option<String>,
     pub accepted_by_is_null: bool,
     pub acceptances_includes_all: Option<Array<String>>,
     pub acceptances_includes_any: Option<Array<String>>,
-    #[serde(flatten)]
     pub pagination: Option<Pagination>,
 }
 
+/// `WalkRequestQuery` as sent on the wire, `nb_serde_query` can't decode
+/// flattened fields.
+#[derive(Deserialize)]
+struct FlatWalkRequestQuery {
+    id: Option<String>,
+    dog_ids_includes_all: Option<Array<String>>,
+    dog_ids_includes_any: Option<Array<String>>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    radius: Option<f64>,
+    accepted_by: Option<String>,
+    accepted_by_neq: Option<String>,
+    accepted_by_is_null: bool,
+    acceptances_includes_all: Option<Array<String>>,
+    acceptances_includes_any: Option<Array<String>>,
+    page: Option<i32>,
+    size: Option<i32>,
+}
+
+impl From<FlatWalkRequestQuery> for WalkRequestQuery {
+    fn from(flat: FlatWalkRequestQuery) -> Self {
+        let nearby = match (flat.latitude, flat.longitude, flat.radius) {
+            (Some(latitude), Some(longitude), Some(radius)) => Some(Nearby {
+                latitude,
+                longitude,
+                radius,
+            }),
+            _ => None,
+        };
+        let pagination = match (flat.page, flat.size) {
+            (Some(page), Some(size)) => Some(Pagination { page, size }),
+            _ => None,
+        };
+        Self {
+            id: flat.id,
+            dog_ids_includes_all: flat.dog_ids_includes_all,
+            dog_ids_includes_any: flat.dog_ids_includes_any,
+            nearby,
+            accepted_by: flat.accepted_by,
+            accepted_by_neq: flat.accepted_by_neq,
+            accepted_by_is_null: flat.accepted_by_is_null,
+            acceptances_includes_all: flat.acceptances_includes_all,
+            acceptances_includes_any: flat.acceptances_includes_any,
+            pagination,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalkRequestCreate {
+    pub dog_ids: Vec<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
 pub trait WalkRequestClient: Clone + 'static {
     async fn query_walk_requests(
         &self,
         query: WalkRequestQuery,
     ) -> Result<Vec<WalkRequest>, Error>;
+
+    async fn create_walk_request(
+        &self,
+        user_id: &str,
+        create: &WalkRequestCreate,
+    ) -> Result<ByteStream, Error>;
+
+    async fn accept_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error>;
+
+    async fn cancel_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error>;
+
+    async fn start_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error>;
+
+    async fn finish_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error>;
 }
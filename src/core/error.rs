@@ -1,35 +1,376 @@
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::error::Error as StdError;
 use std::fmt::Display;
+use std::sync::Arc;
+
+/// What went wrong, `status_code` is the only place a kind is mapped to an
+/// HTTP status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The request is malformed or violates a business rule.
+    Validation,
+    /// The caller could not be identified.
+    Authentication,
+    /// The caller is identified but not allowed to do this.
+    Authorization,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    PayloadTooLarge,
+    UriTooLong,
+    HeadersTooLarge,
+    RateLimited,
+    /// An upstream could not be reached.
+    UpstreamUnavailable,
+    /// An upstream did not answer in time.
+    UpstreamTimeout,
+    /// An upstream failed or answered with something we cannot understand.
+    UpstreamError,
+    /// A bug or misconfiguration in the gateway itself.
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Validation => StatusCode::BAD_REQUEST,
+            Self::Authentication => StatusCode::UNAUTHORIZED,
+            Self::Authorization => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UriTooLong => StatusCode::URI_TOO_LONG,
+            Self::HeadersTooLarge => {
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            }
+            Self::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            Self::UpstreamUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::UpstreamError => StatusCode::BAD_GATEWAY,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Classifies a status produced outside the gateway, e.g. by an upstream
+    /// or by actix itself.
+    pub fn from_status(status_code: u16) -> Self {
+        match status_code {
+            401 => Self::Authentication,
+            403 => Self::Authorization,
+            404 => Self::NotFound,
+            405 => Self::MethodNotAllowed,
+            409 => Self::Conflict,
+            413 => Self::PayloadTooLarge,
+            414 => Self::UriTooLong,
+            431 => Self::HeadersTooLarge,
+            429 => Self::RateLimited,
+            c if (400..500).contains(&c) => Self::Validation,
+            502 => Self::UpstreamError,
+            503 => Self::UpstreamUnavailable,
+            504 => Self::UpstreamTimeout,
+            _ => Self::Internal,
+        }
+    }
+}
+
+/// Stable, machine readable error codes, clients may branch on them so
+/// existing codes must never be renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    BadRequest,
+    InvalidQuery,
+    InvalidBody,
+    MissingContentType,
+    Unauthorized,
+    AuthTokenMissing,
+    AuthTokenInvalid,
+    Forbidden,
+    DogNotOwned,
+    NotFound,
+    RouteNotFound,
+    UserNotFound,
+    MethodNotAllowed,
+    Conflict,
+    PayloadTooLarge,
+    UrlTooLong,
+    HeadersTooLarge,
+    TooManyRequests,
+    SmsCodeInvalid,
+    SmsCodeUsed,
+    SmsLoginAttemptsExceeded,
+    PhoneInvalid,
+    SmsSendCooldown,
+    SmsSendLimitExceeded,
+    LoginLocked,
+    IpDenied,
+    InternalError,
+    UpstreamError,
+    UpstreamUnavailable,
+    UpstreamTimeout,
+}
+
+impl ErrorCode {
+    /// The generic code for a kind, used when no specific code is given.
+    pub fn from_kind(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Validation => Self::BadRequest,
+            ErrorKind::Authentication => Self::Unauthorized,
+            ErrorKind::Authorization => Self::Forbidden,
+            ErrorKind::NotFound => Self::NotFound,
+            ErrorKind::MethodNotAllowed => Self::MethodNotAllowed,
+            ErrorKind::Conflict => Self::Conflict,
+            ErrorKind::PayloadTooLarge => Self::PayloadTooLarge,
+            ErrorKind::UriTooLong => Self::UrlTooLong,
+            ErrorKind::HeadersTooLarge => Self::HeadersTooLarge,
+            ErrorKind::RateLimited => Self::TooManyRequests,
+            ErrorKind::UpstreamUnavailable => Self::UpstreamUnavailable,
+            ErrorKind::UpstreamTimeout => Self::UpstreamTimeout,
+            ErrorKind::UpstreamError => Self::UpstreamError,
+            ErrorKind::Internal => Self::InternalError,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Error {
-    pub status_code: u16,
+    pub kind: ErrorKind,
+    pub code: ErrorCode,
     pub cause: String,
+    pub details: Option<Value>,
+    pub upstream: Option<&'static str>,
+    /// The code of an upstream error body following the error schema.
+    pub upstream_code: Option<String>,
+    source: Option<Arc<dyn StdError + Send + Sync>>,
+}
+
+/// An upstream error body following the error schema, such bodies are meant
+/// for clients and are passed through as is.
+#[derive(Debug, Deserialize)]
+struct UpstreamErrorBody {
+    code: String,
+    message: String,
+    #[serde(default)]
+    details: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum EnvelopeCode<'a> {
+    Gateway(ErrorCode),
+    Upstream(&'a str),
+}
+
+/// The JSON body of every error response.
+#[derive(Debug, Serialize)]
+pub struct ErrorEnvelope<'a> {
+    pub code: EnvelopeCode<'a>,
+    pub message: &'a str,
+    pub request_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<&'a Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<&'a str>,
 }
 
 impl Error {
-    pub fn new<C>(status_code: u16, cause: C) -> Self
+    pub fn new<C>(kind: ErrorKind, cause: C) -> Self
     where
         C: Display,
     {
         Self {
-            status_code,
+            kind,
+            code: ErrorCode::from_kind(kind),
             cause: cause.to_string(),
+            details: None,
+            upstream: None,
+            upstream_code: None,
+            source: None,
         }
     }
 
-    pub fn wrap<C>(status_code: u16) -> impl FnOnce(C) -> Self
+    /// Wraps an underlying error, keeping it as the source.
+    pub fn wrap<E>(kind: ErrorKind) -> impl FnOnce(E) -> Self
+    where
+        E: StdError + Send + Sync + 'static,
+    {
+        move |e| Error::new(kind, &e).with_source(e)
+    }
+
+    /// Wraps a failure to decode the request body.
+    pub fn invalid_body<E>(e: E) -> Self
+    where
+        E: StdError + Send + Sync + 'static,
+    {
+        Error::wrap(ErrorKind::Validation)(e).with_code(ErrorCode::InvalidBody)
+    }
+
+    /// Maps a failure to decode the query string, the query decoder's errors
+    /// are not `Send` so only their message is kept.
+    pub fn invalid_query<C>(cause: C) -> Self
     where
         C: Display,
     {
-        move |c| Error::new(status_code, c)
+        Error::new(ErrorKind::Validation, cause)
+            .with_code(ErrorCode::InvalidQuery)
+    }
+
+    /// Maps a non-success response of `upstream`, upstream server errors
+    /// are reported as upstream errors so they are not mistaken for gateway
+    /// bugs, an unavailable or timed out upstream as such.
+    pub fn from_upstream(
+        upstream: &'static str,
+        status_code: u16,
+        body: &str,
+    ) -> Self {
+        if status_code >= 500 {
+            let kind = match status_code {
+                503 => ErrorKind::UpstreamUnavailable,
+                504 => ErrorKind::UpstreamTimeout,
+                _ => ErrorKind::UpstreamError,
+            };
+            return Error::new(kind, body).with_upstream(upstream);
+        }
+        let kind = ErrorKind::from_status(status_code);
+        match serde_json::from_str::<UpstreamErrorBody>(body) {
+            Ok(body) => Self {
+                details: body.details,
+                upstream_code: Some(body.code),
+                ..Error::new(kind, body.message).with_upstream(upstream)
+            },
+            Err(_) => Error::new(kind, body).with_upstream(upstream),
+        }
+    }
+
+    /// Maps a failure to reach `upstream` at all.
+    pub fn upstream_unreachable(
+        upstream: &'static str,
+        e: reqwest::Error,
+    ) -> Self {
+        let kind = if e.is_timeout() {
+            ErrorKind::UpstreamTimeout
+        } else {
+            ErrorKind::UpstreamUnavailable
+        };
+        Error::wrap(kind)(e).with_upstream(upstream)
+    }
+
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn with_upstream(mut self, upstream: &'static str) -> Self {
+        self.upstream = Some(upstream);
+        self
+    }
+
+    pub fn with_source<E>(mut self, source: E) -> Self
+    where
+        E: StdError + Send + Sync + 'static,
+    {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /// Whether the cause may contain upstream internals, e.g. stack traces
+    /// or hostnames, which must not reach clients.
+    pub fn is_sensitive(&self) -> bool {
+        self.upstream.is_some() && self.upstream_code.is_none()
+    }
+
+    /// Builds the envelope, `localized` is the catalog message for the code
+    /// and is shown unless the upstream provided its own message or
+    /// sanitizing is off.
+    pub fn envelope<'a>(
+        &'a self,
+        request_id: Option<&'a str>,
+        sanitize: bool,
+        localized: &'a str,
+    ) -> ErrorEnvelope<'a> {
+        let code = match &self.upstream_code {
+            Some(code) => EnvelopeCode::Upstream(code),
+            None => EnvelopeCode::Gateway(self.code),
+        };
+        let sanitized = sanitize && self.is_sensitive();
+        let message = if self.upstream_code.is_some()
+            || (!sanitize && self.is_sensitive())
+        {
+            &self.cause
+        } else {
+            localized
+        };
+        ErrorEnvelope {
+            code,
+            message,
+            request_id,
+            details: if sanitized {
+                None
+            } else {
+                self.details.as_ref()
+            },
+            upstream: self.upstream,
+        }
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "status: {}, cause: {}", self.status_code, self.cause)
+        write!(f, "{:?}: {}", self.kind, self.cause)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_deref()
+            .map(|e| e as &(dyn StdError + 'static))
     }
 }
 
-impl StdError for Error {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstream_statuses_map_to_kinds() {
+        for (status, kind) in [
+            (400, ErrorKind::Validation),
+            (401, ErrorKind::Authentication),
+            (404, ErrorKind::NotFound),
+            (422, ErrorKind::Validation),
+            (500, ErrorKind::UpstreamError),
+            (502, ErrorKind::UpstreamError),
+            (503, ErrorKind::UpstreamUnavailable),
+            (504, ErrorKind::UpstreamTimeout),
+        ] {
+            let e = Error::from_upstream("dog", status, "boom");
+            assert_eq!(e.kind, kind, "{}", status);
+            assert_eq!(e.code, ErrorCode::from_kind(kind));
+            assert_eq!(e.upstream, Some("dog"));
+        }
+        assert_eq!(
+            ErrorKind::UpstreamError.status_code(),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[test]
+    fn wrapped_errors_are_kept_as_source() {
+        let e = serde_json::from_str::<Value>("{").unwrap_err();
+        let message = e.to_string();
+        let e = Error::invalid_body(e);
+        assert_eq!(e.kind, ErrorKind::Validation);
+        assert_eq!(e.code, ErrorCode::InvalidBody);
+        assert_eq!(e.source().unwrap().to_string(), message);
+        assert!(Error::new(ErrorKind::Internal, "bug").source().is_none());
+    }
+}
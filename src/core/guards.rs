@@ -0,0 +1,254 @@
+use futures::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::core::error::{Error, ErrorCode, ErrorKind};
+use crate::state::{StateBackend, StateStore};
+
+/// Counts attempts per key, the counter is cleared once `window` has elapsed
+/// since the first attempt.
+#[derive(Clone)]
+pub struct AttemptCounter {
+    state: StateBackend,
+    namespace: &'static str,
+    max_attempts: u32,
+    window: Duration,
+}
+
+impl AttemptCounter {
+    pub fn new(
+        state: StateBackend,
+        namespace: &'static str,
+        max_attempts: u32,
+        window: Duration,
+    ) -> Self {
+        Self {
+            state,
+            namespace,
+            max_attempts,
+            window,
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:{}", self.namespace, key)
+    }
+
+    pub async fn count(&self, key: &str) -> Result<u32, Error> {
+        self.state.count(&self.key(key)).await
+    }
+
+    /// Returns the attempts so far, this one included.
+    pub async fn record(&self, key: &str) -> Result<u32, Error> {
+        self.state.increment(&self.key(key), self.window).await
+    }
+
+    /// Records an attempt, returns `false` if it is beyond the limit. The
+    /// count is the gate, so concurrent attempts cannot all slip through.
+    pub async fn try_record(&self, key: &str) -> Result<bool, Error> {
+        Ok(self.record(key).await? <= self.max_attempts)
+    }
+
+    /// Takes back an attempt recorded before, e.g. one that did not happen.
+    pub async fn refund(&self, key: &str) -> Result<(), Error> {
+        self.state.decrement(&self.key(key)).await
+    }
+
+    pub async fn reset(&self, key: &str) -> Result<(), Error> {
+        self.state.remove(&self.key(key)).await
+    }
+}
+
+/// Remembers keys for `ttl` so that each key can be consumed only once.
+#[derive(Clone)]
+pub struct ConsumedKeys {
+    state: StateBackend,
+    namespace: &'static str,
+    ttl: Duration,
+}
+
+impl ConsumedKeys {
+    pub fn new(
+        state: StateBackend,
+        namespace: &'static str,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            state,
+            namespace,
+            ttl,
+        }
+    }
+
+    /// Returns `false` if the key has already been consumed.
+    pub async fn consume(&self, key: &str) -> Result<bool, Error> {
+        let key = format!("{}:{}", self.namespace, key);
+        self.state.insert_if_absent(&key, self.ttl).await
+    }
+
+    /// Makes a consumed key available again.
+    pub async fn release(&self, key: &str) -> Result<(), Error> {
+        let key = format!("{}:{}", self.namespace, key);
+        self.state.remove(&key).await
+    }
+}
+
+pub struct LoginGuardOptions {
+    /// Failures within `failure_window` before a phone is locked.
+    pub max_failures_per_phone: u32,
+    /// Failures within `failure_window` before an IP is locked.
+    pub max_failures_per_ip: u32,
+    pub failure_window: Duration,
+    pub lockout: Duration,
+    /// Failures answered without delay, later ones wait `delay`, doubled
+    /// for each further failure up to `max_delay`.
+    pub delay_after_failures: u32,
+    pub delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// Slows down and then locks out logins failing repeatedly for a phone or
+/// from an IP.
+#[derive(Clone)]
+pub struct LoginGuard {
+    state: StateBackend,
+    phone_failures: AttemptCounter,
+    ip_failures: AttemptCounter,
+    options: Arc<LoginGuardOptions>,
+}
+
+/// A failure counted for an attempt in flight: the counter, the kind and key
+/// of what it counts and the failures so far, this attempt included.
+type Reservation<'a> = (&'a AttemptCounter, &'static str, &'a str, u32);
+
+/// Whether `error` means the credentials were wrong, rather than the login
+/// could not be checked.
+fn is_credential_failure(error: &Error) -> bool {
+    matches!(
+        error.kind,
+        ErrorKind::Validation | ErrorKind::Authentication | ErrorKind::NotFound
+    )
+}
+
+impl LoginGuard {
+    pub fn new(state: StateBackend, options: LoginGuardOptions) -> Self {
+        Self {
+            phone_failures: AttemptCounter::new(
+                state.clone(),
+                "login_failures:phone",
+                options.max_failures_per_phone,
+                options.failure_window,
+            ),
+            ip_failures: AttemptCounter::new(
+                state.clone(),
+                "login_failures:ip",
+                options.max_failures_per_ip,
+                options.failure_window,
+            ),
+            state,
+            options: Arc::new(options),
+        }
+    }
+
+    fn lock_key(kind: &str, id: &str) -> String {
+        format!("login_locks:{}:{}", kind, id)
+    }
+
+    fn delay(&self, failures: u32) -> Duration {
+        let Some(excess) =
+            failures.checked_sub(self.options.delay_after_failures)
+        else {
+            return Duration::ZERO;
+        };
+        self.options
+            .delay
+            .saturating_mul(2u32.saturating_pow(excess))
+            .min(self.options.max_delay)
+    }
+
+    /// Runs `login` unless the phone or IP is locked. Credential failures
+    /// are counted and lock once there are too many, a success clears the
+    /// failures of the phone. Those of the IP are kept, lest one valid
+    /// account unlocks guessing the others.
+    ///
+    /// The attempt is counted as a failure before `login` runs, so that
+    /// concurrent guesses cannot all pass, and given back unless it fails.
+    pub async fn attempt<T, F>(
+        &self,
+        phone: &str,
+        ip: Option<&str>,
+        login: F,
+    ) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        let locked = || {
+            Err(Error::new(ErrorKind::RateLimited, "too many failed logins")
+                .with_code(ErrorCode::LoginLocked))
+        };
+        let mut counters = vec![(&self.phone_failures, "phone", phone)];
+        counters.extend(ip.map(|ip| (&self.ip_failures, "ip", ip)));
+        for (_, kind, key) in &counters {
+            if self.state.count(&Self::lock_key(kind, key)).await? > 0 {
+                return locked();
+            }
+        }
+        let mut reserved: Vec<Reservation> = Vec::new();
+        for (counter, kind, key) in counters {
+            let failures = counter.record(key).await?;
+            reserved.push((counter, kind, key, failures));
+            if failures > counter.max_attempts {
+                Self::refund(&reserved).await?;
+                return locked();
+            }
+        }
+        let failures = reserved
+            .iter()
+            .map(|(.., failures)| failures - 1)
+            .max()
+            .unwrap_or_default();
+        let delay = self.delay(failures);
+        if !delay.is_zero() {
+            actix_web::rt::time::sleep(delay).await;
+        }
+        let result = login.await;
+        match &result {
+            Ok(_) => {
+                self.phone_failures.reset(phone).await?;
+                reserved.retain(|(_, kind, ..)| *kind != "phone");
+                Self::refund(&reserved).await?;
+            }
+            Err(e) if is_credential_failure(e) => {
+                self.lock_exhausted(&reserved).await?
+            }
+            Err(_) => Self::refund(&reserved).await?,
+        }
+        result
+    }
+
+    async fn refund(reserved: &[Reservation<'_>]) -> Result<(), Error> {
+        for (counter, _, key, _) in reserved {
+            counter.refund(key).await?;
+        }
+        Ok(())
+    }
+
+    /// Locks what reached its limit of failures with this one.
+    async fn lock_exhausted(
+        &self,
+        reserved: &[Reservation<'_>],
+    ) -> Result<(), Error> {
+        for (counter, kind, key, failures) in reserved {
+            if *failures >= counter.max_attempts {
+                counter.reset(key).await?;
+                self.state
+                    .insert_if_absent(
+                        &Self::lock_key(kind, key),
+                        self.options.lockout,
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
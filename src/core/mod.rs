@@ -2,5 +2,6 @@ pub mod clients;
 pub mod common;
 pub mod entities;
 pub mod error;
+pub mod guards;
 pub mod requests;
 pub mod service;
@@ -1,6 +1,6 @@
 use super::common::Pagination;
 use chrono::{DateTime, Utc};
-use little_walk_dog::core::repository::BreedQuery;
+use little_walk_dog::core::repository::{BreedQuery, DogCreate};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, Serialize)]
@@ -11,7 +11,7 @@ pub struct DogQuery {
     pub pagination: Option<Pagination>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DogPortraitUpdate {
     pub portrait_id: String,
 }
@@ -27,3 +27,18 @@ pub struct DogCreateIncome {
     pub tags: Vec<String>,
     pub portrait_id: Option<String>,
 }
+
+impl DogCreateIncome {
+    /// The dog service request creating the dog for `owner_id`.
+    pub fn into_create(self, owner_id: String) -> DogCreate {
+        DogCreate {
+            owner_id,
+            name: self.name,
+            gender: self.gender,
+            breed: self.breed,
+            birthday: self.birthday,
+            tags: self.tags,
+            portrait_id: self.portrait_id,
+        }
+    }
+}
@@ -1,22 +1,158 @@
-use crate::{core::error::Error, utils::restful::UserID};
+use crate::{
+    core::error::{Error, ErrorCode, ErrorKind},
+    state::StateBackend,
+    utils::{io::stream_to_bytes, restful::UserID, telemetry},
+};
 use actix_web::{FromRequest, HttpRequest};
 use bytes::Bytes;
-use futures::{future::ready, Future, Stream};
-use little_walk_dog::core::repository::DogCreate;
-use reqwest::StatusCode;
+use futures::{
+    future::{ready, try_join_all},
+    Future, Stream,
+};
+use regex::Regex;
 use std::pin::Pin;
+use std::time::Duration;
 
-use super::requests::DogCreateIncome;
+use super::clients::{
+    auth::AuthClient,
+    dog::DogClient,
+    sms_verification_code::SMSVerificationCodeClient,
+    upload::{Download, UploadClient},
+    walk_request::{self, WalkRequestClient, WalkRequestCreate},
+};
+use super::common::Pagination;
+use super::entities::WalkRequest;
+use super::guards::{
+    AttemptCounter, ConsumedKeys, LoginGuard, LoginGuardOptions,
+};
+use super::requests::{DogCreateIncome, DogQuery};
 
 pub type ByteStream =
     Pin<Box<dyn Stream<Item = Result<Bytes, Error>> + Send + Sync>>;
 
+pub struct SMSLoginOptions {
+    pub max_attempts: u32,
+    pub attempt_window: Duration,
+    /// How long codes stay valid, hence remembered as consumed.
+    pub code_ttl: Duration,
+    pub auto_signup: bool,
+}
+
+pub struct SMSSendOptions {
+    pub phone_pattern: Regex,
+    pub cooldown: Duration,
+    pub daily_limit_per_phone: u32,
+    pub daily_limit_per_ip: u32,
+    /// Phones that are never sent a code, `test_code` is valid for them.
+    pub test_phones: Vec<String>,
+    pub test_code: String,
+}
+
+pub struct WalkRequestOptions {
+    /// Radius of nearby queries that don't give one, in meters.
+    pub nearby_radius: f64,
+}
+
+pub struct ServiceOptions {
+    pub sms_login: SMSLoginOptions,
+    pub sms_send: SMSSendOptions,
+    pub login_guard: LoginGuardOptions,
+    pub walk_request: WalkRequestOptions,
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
 #[derive(Clone)]
-pub struct Service;
+pub struct Service<A, S, D, W, U>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    auth_client: A,
+    sms_verification_code_client: S,
+    dog_client: D,
+    walk_request_client: W,
+    upload_client: U,
+    sms_login_attempts: AttemptCounter,
+    consumed_sms_verification_codes: ConsumedKeys,
+    sms_login_auto_signup: bool,
+    sms_phone_pattern: Regex,
+    sms_send_cooldowns: ConsumedKeys,
+    sms_sends_per_phone: AttemptCounter,
+    sms_sends_per_ip: AttemptCounter,
+    sms_test_phones: Vec<String>,
+    sms_test_code: String,
+    login_guard: LoginGuard,
+    nearby_radius: f64,
+}
 
-impl Service {
-    pub fn new() -> Self {
-        Self {}
+impl<A, S, D, W, U> Service<A, S, D, W, U>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    pub fn new(
+        auth_client: A,
+        sms_verification_code_client: S,
+        dog_client: D,
+        walk_request_client: W,
+        upload_client: U,
+        options: ServiceOptions,
+        state: StateBackend,
+    ) -> Self {
+        let ServiceOptions {
+            sms_login: sms_login_options,
+            sms_send: sms_send_options,
+            login_guard: login_guard_options,
+            walk_request: walk_request_options,
+        } = options;
+        Self {
+            auth_client,
+            sms_verification_code_client,
+            dog_client,
+            walk_request_client,
+            upload_client,
+            sms_login_attempts: AttemptCounter::new(
+                state.clone(),
+                "sms_login_attempts",
+                sms_login_options.max_attempts,
+                sms_login_options.attempt_window,
+            ),
+            consumed_sms_verification_codes: ConsumedKeys::new(
+                state.clone(),
+                "consumed_sms_verification_codes",
+                sms_login_options.code_ttl,
+            ),
+            sms_login_auto_signup: sms_login_options.auto_signup,
+            sms_phone_pattern: sms_send_options.phone_pattern,
+            sms_send_cooldowns: ConsumedKeys::new(
+                state.clone(),
+                "sms_send_cooldowns",
+                sms_send_options.cooldown,
+            ),
+            sms_sends_per_phone: AttemptCounter::new(
+                state.clone(),
+                "sms_sends_per_phone",
+                sms_send_options.daily_limit_per_phone,
+                DAY,
+            ),
+            sms_sends_per_ip: AttemptCounter::new(
+                state.clone(),
+                "sms_sends_per_ip",
+                sms_send_options.daily_limit_per_ip,
+                DAY,
+            ),
+            sms_test_phones: sms_send_options.test_phones,
+            sms_test_code: sms_send_options.test_code,
+            login_guard: LoginGuard::new(state.clone(), login_guard_options),
+            nearby_radius: walk_request_options.nearby_radius,
+        }
     }
 
     // pub async fn signup(
@@ -35,41 +171,118 @@ impl Service {
     //             "invalid sms verification code",
     //         ));
     //     }
-    //     self.auth_client.signup(phone, password).await
+    //     self.auth_client
+    //         .signup(phone, verification_code, password)
+    //         .await
     // }
 
-    // pub async fn login_by_password(
-    //     &self,
-    //     phone: &str,
-    //     password: &str,
-    // ) -> Result<ByteStream, Error> {
-    //     self.auth_client.login(phone, password).await
-    // }
+    pub async fn login_by_password(
+        &self,
+        phone: &str,
+        password: &str,
+        client_ip: Option<&str>,
+    ) -> Result<ByteStream, Error> {
+        self.login_guard
+            .attempt(phone, client_ip, self.auth_client.login(phone, password))
+            .await
+    }
 
-    // pub async fn login_by_sms_verification_code(
-    //     &self,
-    //     phone: &str,
-    //     verification_code: &str,
-    // ) -> Result<ByteStream, Error> {
-    //     let exists = self.auth_client.exists_user(phone).await?;
-    //     if !exists {
-    //         return Err(Error::new(
-    //             StatusCode::NOT_FOUND.as_u16(),
-    //             "user not exists",
-    //         ));
-    //     }
-    //     let ok = self
-    //         .sms_verification_code_client
-    //         .verify_code(phone, verification_code)
-    //         .await?;
-    //     if !ok {
-    //         return Err(Error::new(
-    //             StatusCode::BAD_REQUEST.as_u16(),
-    //             "invalid sms verification code",
-    //         ));
-    //     }
-    //     self.auth_client.generate_token(phone).await
-    // }
+    pub async fn login_by_sms_verification_code(
+        &self,
+        phone: &str,
+        verification_code: &str,
+        client_ip: Option<&str>,
+    ) -> Result<ByteStream, Error> {
+        self.login_guard
+            .attempt(
+                phone,
+                client_ip,
+                self.verify_sms_login(phone, verification_code),
+            )
+            .await
+    }
+
+    /// Checks the code before anything about the phone is revealed. The
+    /// code is consumed up front, so that it is used once even by
+    /// concurrent logins, and released again if no token is issued.
+    async fn verify_sms_login(
+        &self,
+        phone: &str,
+        verification_code: &str,
+    ) -> Result<ByteStream, Error> {
+        if !self.sms_login_attempts.try_record(phone).await? {
+            return Err(Error::new(
+                ErrorKind::RateLimited,
+                "too many sms verification code login attempts",
+            )
+            .with_code(ErrorCode::SmsLoginAttemptsExceeded));
+        }
+        let is_test_phone = self.is_test_phone(phone);
+        let ok = if is_test_phone {
+            verification_code == self.sms_test_code
+        } else {
+            self.sms_verification_code_client
+                .verify_code(phone, verification_code)
+                .await?
+        };
+        if !ok {
+            return Err(Error::new(
+                ErrorKind::Validation,
+                "invalid sms verification code",
+            )
+            .with_code(ErrorCode::SmsCodeInvalid));
+        }
+        let code_key = format!("{}:{}", phone, verification_code);
+        if !is_test_phone
+            && !self
+                .consumed_sms_verification_codes
+                .consume(&code_key)
+                .await?
+        {
+            return Err(Error::new(
+                ErrorKind::Validation,
+                "sms verification code has been used",
+            )
+            .with_code(ErrorCode::SmsCodeUsed));
+        }
+        let result = self.issue_token(phone, verification_code).await;
+        match &result {
+            Ok(_) => self.sms_login_attempts.reset(phone).await?,
+            Err(_) if !is_test_phone => {
+                if let Err(e) = self
+                    .consumed_sms_verification_codes
+                    .release(&code_key)
+                    .await
+                {
+                    log::warn!("failed to release an sms code: {}", e);
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Issues a token for a phone whose code was verified, signing it up
+    /// with the code and a random password first if allowed.
+    async fn issue_token(
+        &self,
+        phone: &str,
+        verification_code: &str,
+    ) -> Result<ByteStream, Error> {
+        if !self.auth_client.exists_user(phone).await? {
+            if !self.sms_login_auto_signup {
+                return Err(Error::new(ErrorKind::NotFound, "user not exists")
+                    .with_code(ErrorCode::UserNotFound));
+            }
+            let password = uuid::Uuid::new_v4().to_string();
+            let body = self
+                .auth_client
+                .signup(phone, verification_code, &password)
+                .await?;
+            stream_to_bytes(body).await?;
+        }
+        self.auth_client.generate_token(phone).await
+    }
 
     // pub async fn verify_auth_token(
     //     &self,
@@ -78,36 +291,102 @@ impl Service {
     //     self.auth_client.verify_token(token).await
     // }
 
-    // pub async fn add_dog(
-    //     &self,
-    //     owner_id: &str,
-    //     dog: ByteStream,
-    // ) -> Result<ByteStream, Error> {
-    //     self.dog_client.add_dog(owner_id, dog).await
-    // }
+    pub async fn add_dog(
+        &self,
+        owner_id: &str,
+        dog: ByteStream,
+    ) -> Result<ByteStream, Error> {
+        self.dog_client.add_dog(owner_id, dog).await
+    }
 
-    // pub async fn send_verification_code(
-    //     &self,
-    //     phone: &str,
-    // ) -> Result<ByteStream, Error> {
-    //     self.sms_verification_code_client.send_code(phone).await
-    // }
+    fn is_test_phone(&self, phone: &str) -> bool {
+        self.sms_test_phones.iter().any(|p| p == phone)
+    }
 
-    // pub async fn upload(
-    //     &self,
-    //     content_type_header: &str,
-    //     user_id: &str,
-    //     size_limit: usize,
-    //     payload: ByteStream,
-    // ) -> Result<ByteStream, Error> {
-    //     self.upload_client
-    //         .upload(content_type_header, user_id, size_limit, payload)
-    //         .await
-    // }
+    /// Sends a code unless the phone is malformed, was sent one within the
+    /// cooldown, or the phone or `client_ip` reached the daily limit.
+    pub async fn send_verification_code(
+        &self,
+        phone: &str,
+        client_ip: Option<&str>,
+    ) -> Result<ByteStream, Error> {
+        if self.is_test_phone(phone) {
+            let body =
+                serde_json::to_vec(&serde_json::json!({ "phone": phone }))
+                    .map_err(Error::wrap(ErrorKind::Internal))?;
+            return Ok(Box::pin(futures::stream::iter([Ok(body.into())])));
+        }
+        if !self.sms_phone_pattern.is_match(phone) {
+            return Err(Error::new(ErrorKind::Validation, "invalid phone")
+                .with_code(ErrorCode::PhoneInvalid));
+        }
+        if !self.sms_send_cooldowns.consume(phone).await? {
+            return Err(Error::new(
+                ErrorKind::RateLimited,
+                "sms sent to the phone recently",
+            )
+            .with_code(ErrorCode::SmsSendCooldown));
+        }
+        let result = self.send_counted_code(phone, client_ip).await;
+        if result.is_err() {
+            self.sms_send_cooldowns.release(phone).await?;
+        }
+        result
+    }
 
-    // pub async fn download(&self, id: &str) -> Result<ByteStream, Error> {
-    //     self.upload_client.download(id).await
-    // }
+    /// Sends a code within the daily limits. The sends are counted before,
+    /// lest concurrent ones all pass, and refunded unless the code is sent.
+    async fn send_counted_code(
+        &self,
+        phone: &str,
+        client_ip: Option<&str>,
+    ) -> Result<ByteStream, Error> {
+        let mut counted = Vec::new();
+        let mut within_limits = true;
+        for (counter, key) in [
+            (&self.sms_sends_per_phone, Some(phone)),
+            (&self.sms_sends_per_ip, client_ip),
+        ] {
+            let Some(key) = key else { continue };
+            within_limits = counter.try_record(key).await?;
+            counted.push((counter, key));
+            if !within_limits {
+                break;
+            }
+        }
+        let result = if within_limits {
+            self.sms_verification_code_client.send_code(phone).await
+        } else {
+            Err(Error::new(ErrorKind::RateLimited, "too many sms sent")
+                .with_code(ErrorCode::SmsSendLimitExceeded))
+        };
+        if result.is_err() {
+            for (counter, key) in counted {
+                counter.refund(key).await?;
+            }
+        }
+        result
+    }
+
+    pub async fn upload(
+        &self,
+        content_type_header: &str,
+        user_id: &str,
+        size_limit: usize,
+        payload: ByteStream,
+    ) -> Result<ByteStream, Error> {
+        self.upload_client
+            .upload(content_type_header, user_id, size_limit, payload)
+            .await
+    }
+
+    pub async fn download(&self, id: &str) -> Result<Download, Error> {
+        self.upload_client.download(id).await
+    }
+
+    pub async fn dogs_of_owner(&self, uid: &str) -> Result<ByteStream, Error> {
+        self.dog_client.dogs_by_owner_id(uid).await
+    }
 
     // pub async fn my_dogs(
     //     &self,
@@ -124,80 +403,130 @@ impl Service {
     //         .await
     // }
 
-    // pub async fn update_dog_portrait(
-    //     &self,
-    //     uid: &str,
-    //     dog_id: &str,
-    //     portrait_id: &str,
-    // ) -> Result<ByteStream, Error> {
-    //     let is_owner = self.dog_client.is_owner_of_the_dog(uid, dog_id).await?;
-    //     if !is_owner {
-    //         return Err(Error::new(
-    //             StatusCode::FORBIDDEN.as_u16(),
-    //             "no permission",
-    //         ));
-    //     }
-    //     self.dog_client
-    //         .update_dog_portrait(dog_id, portrait_id)
-    //         .await
-    // }
+    pub async fn update_dog_portrait(
+        &self,
+        uid: &str,
+        dog_id: &str,
+        portrait_id: &str,
+    ) -> Result<ByteStream, Error> {
+        let is_owner = self.dog_client.is_owner_of_the_dog(uid, dog_id).await?;
+        if !is_owner {
+            return Err(Error::new(ErrorKind::Authorization, "no permission")
+                .with_code(ErrorCode::DogNotOwned));
+        }
+        self.dog_client
+            .update_dog_portrait(dog_id, portrait_id)
+            .await
+    }
 
-    // pub async fn dog_breeds(
-    //     &self,
-    //     category: &str,
-    // ) -> Result<ByteStream, Error> {
-    //     self.dog_client.query_breeds(category).await
-    // }
+    pub async fn dog_breeds(
+        &self,
+        category: &str,
+    ) -> Result<ByteStream, Error> {
+        self.dog_client.query_breeds(category).await
+    }
 
-    // pub async fn update_dog(
-    //     &self,
-    //     uid: &str,
-    //     dog_id: &str,
-    //     req_body: Bytes,
-    // ) -> Result<ByteStream, Error> {
-    //     let is_owner = self.dog_client.is_owner_of_the_dog(uid, dog_id).await?;
-    //     if !is_owner {
-    //         return Err(Error::new(
-    //             StatusCode::FORBIDDEN.as_u16(),
-    //             "no permission",
-    //         ));
-    //     }
-    //     self.dog_client.update_dog(dog_id, req_body).await
-    // }
+    pub async fn update_dog(
+        &self,
+        uid: &str,
+        dog_id: &str,
+        req_body: Bytes,
+    ) -> Result<ByteStream, Error> {
+        let is_owner = self.dog_client.is_owner_of_the_dog(uid, dog_id).await?;
+        if !is_owner {
+            return Err(Error::new(ErrorKind::Authorization, "no permission")
+                .with_code(ErrorCode::DogNotOwned));
+        }
+        self.dog_client.update_dog(dog_id, req_body).await
+    }
 
-    // pub async fn nearby_requests(
-    //     &self,
-    //     longitude: f64,
-    //     latitude: f64,
-    //     radius: f64,
-    //     pagination: Pagination,
-    // ) -> Result<Vec<WalkRequest>, Error> {
-    //     let fs = self
-    //         .walk_request_client
-    //         .query_walk_requests(walk_request::WalkRequestQuery {
-    //             nearby: Some(walk_request::Nearby {
-    //                 latitude,
-    //                 longitude,
-    //                 radius,
-    //             }),
-    //             pagination: Some(pagination),
-    //             ..Default::default()
-    //         })
-    //         .await?
-    //         .into_iter()
-    //         .map(|r| async move {
-    //             let dogs = self
-    //                 .dog_client
-    //                 .query_dogs(&DogQuery {
-    //                     id_in: Some(r.dog_ids.clone()),
-    //                     ..Default::default()
-    //                 })
-    //                 .await?;
-    //             Ok(WalkRequest::from((r, dogs)))
-    //         })
-    //         .collect::<Vec<_>>();
-    //     try_join_all(fs).await
-    // }
+    pub async fn nearby_requests(
+        &self,
+        longitude: f64,
+        latitude: f64,
+        radius: Option<f64>,
+        pagination: Pagination,
+    ) -> Result<Vec<WalkRequest>, Error> {
+        let radius = radius.unwrap_or(self.nearby_radius);
+        telemetry::in_span("walk_requests.nearby", async {
+            let fs = telemetry::in_span(
+                "walk_requests.nearby.query",
+                self.walk_request_client.query_walk_requests(
+                    walk_request::WalkRequestQuery {
+                        nearby: Some(walk_request::Nearby {
+                            latitude,
+                            longitude,
+                            radius,
+                        }),
+                        pagination: Some(pagination),
+                        ..Default::default()
+                    },
+                ),
+            )
+            .await?
+            .into_iter()
+            .map(|r| {
+                telemetry::in_span(
+                    "walk_requests.nearby.fill_dogs",
+                    async move {
+                        let dogs = self
+                            .dog_client
+                            .query_dogs(&DogQuery {
+                                id_in: Some(r.dog_ids.clone()),
+                                ..Default::default()
+                            })
+                            .await?;
+                        Ok(WalkRequest::from((r, dogs)))
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+            try_join_all(fs).await
+        })
+        .await
+    }
+
+    pub async fn create_walk_request(
+        &self,
+        uid: &str,
+        create: &WalkRequestCreate,
+    ) -> Result<ByteStream, Error> {
+        self.walk_request_client
+            .create_walk_request(uid, create)
+            .await
+    }
+
+    pub async fn accept_walk_request(
+        &self,
+        uid: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.walk_request_client.accept_walk_request(uid, id).await
+    }
+
+    pub async fn cancel_walk_request(
+        &self,
+        uid: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.walk_request_client.cancel_walk_request(uid, id).await
+    }
+
+    pub async fn start_walk_request(
+        &self,
+        uid: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.walk_request_client.start_walk_request(uid, id).await
+    }
+
+    pub async fn finish_walk_request(
+        &self,
+        uid: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.walk_request_client.finish_walk_request(uid, id).await
+    }
 
     // pub(crate) fn fill_dogs_processor(
     //     &self,
@@ -268,26 +597,534 @@ impl Service {
                 let user_id_future = UserID::extract(req);
                 Box::pin(async move {
                     let user_id = user_id_future.await?;
-                    let create = DogCreate {
-                        owner_id: user_id.0,
-                        name: income.name,
-                        gender: income.gender,
-                        breed: income.breed,
-                        birthday: income.birthday,
-                        tags: income.tags,
-                        portrait_id: income.portrait_id,
-                    };
-                    let bytes: Bytes =
-                        serde_json::to_vec(&create).map(|v| v.into()).map_err(
-                            |e| Error::new(StatusCode::BAD_REQUEST.as_u16(), e),
-                        )?;
+                    let create = income.into_create(user_id.0);
+                    let bytes: Bytes = serde_json::to_vec(&create)
+                        .map(|v| v.into())
+                        .map_err(Error::wrap(ErrorKind::Internal))?;
                     Ok(bytes)
                 })
             }
-            Err(e) => Box::pin(ready(Err(Error::new(
-                StatusCode::BAD_REQUEST.as_u16(),
-                e,
-            )))),
+            Err(e) => Box::pin(ready(Err(Error::invalid_body(e)))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::clients::dog::{Breed, Dog};
+    use crate::core::clients::walk_request::WalkRequest as RawWalkRequest;
+    use crate::fakes::{
+        auth::FakeAuthClient, dog::FakeDogClient, json_stream,
+        sms_verification_code::FakeSMSVerificationCodeClient,
+        upload::FakeUploadClient, walk_request::FakeWalkRequestClient,
+    };
+    use chrono::Utc;
+    use serde_json::json;
+
+    type TestService = Service<
+        FakeAuthClient,
+        FakeSMSVerificationCodeClient,
+        FakeDogClient,
+        FakeWalkRequestClient,
+        FakeUploadClient,
+    >;
+
+    fn service(
+        auth_client: FakeAuthClient,
+        sms_verification_code_client: FakeSMSVerificationCodeClient,
+        dog_client: FakeDogClient,
+        walk_request_client: FakeWalkRequestClient,
+        auto_signup: bool,
+    ) -> TestService {
+        Service::new(
+            auth_client,
+            sms_verification_code_client,
+            dog_client,
+            walk_request_client,
+            FakeUploadClient::new(),
+            ServiceOptions {
+                sms_login: SMSLoginOptions {
+                    max_attempts: 2,
+                    attempt_window: Duration::from_secs(60),
+                    code_ttl: Duration::from_secs(60),
+                    auto_signup,
+                },
+                sms_send: SMSSendOptions {
+                    phone_pattern: Regex::new("^1[3-9][0-9]{9}$").unwrap(),
+                    cooldown: Duration::from_secs(60),
+                    daily_limit_per_phone: 2,
+                    daily_limit_per_ip: 2,
+                    test_phones: vec!["10000000000".to_owned()],
+                    test_code: "654321".to_owned(),
+                },
+                login_guard: LoginGuardOptions {
+                    max_failures_per_phone: 3,
+                    max_failures_per_ip: 5,
+                    failure_window: Duration::from_secs(60),
+                    lockout: Duration::from_secs(60),
+                    delay_after_failures: 1,
+                    delay: Duration::from_millis(10),
+                    max_delay: Duration::from_millis(20),
+                },
+                walk_request: WalkRequestOptions {
+                    nearby_radius: 1000.0,
+                },
+            },
+            StateBackend::memory(),
+        )
+    }
+
+    fn dog(id: &str, owner_id: &str) -> Dog {
+        Dog {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            gender: "Male".to_owned(),
+            breed: Breed {
+                id: "border".to_owned(),
+                category: "dog".to_owned(),
+                name: "Border".to_owned(),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            },
+            birthday: Utc::now(),
+            is_sterilized: false,
+            introduction: String::new(),
+            owner_id: owner_id.to_owned(),
+            tags: vec![],
+            portrait_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn sms_verification_code_is_consumed_once() {
+        let auth_client = FakeAuthClient::new().with_user("1", "139", "pw");
+        let service = service(
+            auth_client.clone(),
+            FakeSMSVerificationCodeClient::new().with_code("139", "123456"),
+            FakeDogClient::new(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        let token = service
+            .login_by_sms_verification_code("139", "123456", None)
+            .await
+            .unwrap();
+        assert!(!stream_to_bytes(token).await.unwrap().is_empty());
+        let err = service
+            .login_by_sms_verification_code("139", "123456", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::Validation);
+        assert_eq!(auth_client.script.calls_to("generate_token").len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn sms_login_attempts_are_limited_per_phone() {
+        let sms_client =
+            FakeSMSVerificationCodeClient::new().with_code("139", "123456");
+        let service = service(
+            FakeAuthClient::new().with_user("1", "139", "pw"),
+            sms_client.clone(),
+            FakeDogClient::new(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        for _ in 0..2 {
+            let err = service
+                .login_by_sms_verification_code("139", "000000", None)
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(err.kind, ErrorKind::Validation);
+        }
+        let err = service
+            .login_by_sms_verification_code("139", "123456", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::RateLimited);
+        assert_eq!(sms_client.script.calls_to("verify_code").len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn sms_sends_are_validated_and_limited() {
+        let sms_client = FakeSMSVerificationCodeClient::new();
+        let service = service(
+            FakeAuthClient::new().with_user("1", "10000000000", "pw"),
+            sms_client.clone(),
+            FakeDogClient::new(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        let code_of = |result: Result<ByteStream, Error>| {
+            result.err().map(|e| (e.kind, e.code))
+        };
+        assert_eq!(
+            code_of(service.send_verification_code("139", None).await),
+            Some((ErrorKind::Validation, ErrorCode::PhoneInvalid))
+        );
+        assert_eq!(
+            code_of(service.send_verification_code("13900000001", None).await),
+            None
+        );
+        assert_eq!(
+            code_of(service.send_verification_code("13900000001", None).await),
+            Some((ErrorKind::RateLimited, ErrorCode::SmsSendCooldown))
+        );
+        assert_eq!(
+            service
+                .sms_sends_per_phone
+                .count("13900000001")
+                .await
+                .unwrap(),
+            1
+        );
+        for phone in ["13900000002", "13900000003"] {
+            let result =
+                service.send_verification_code(phone, Some("ip")).await;
+            assert_eq!(code_of(result), None);
+        }
+        assert_eq!(
+            code_of(
+                service
+                    .send_verification_code("13900000004", Some("ip"))
+                    .await
+            ),
+            Some((ErrorKind::RateLimited, ErrorCode::SmsSendLimitExceeded))
+        );
+        assert_eq!(sms_client.script.calls_to("send_code").len(), 3);
+
+        // Only sends attempted are counted, failed ones are refunded and
+        // leave no cooldown.
+        sms_client
+            .script
+            .fail_next("send_code", Error::new(ErrorKind::UpstreamError, ""));
+        assert_eq!(
+            code_of(service.send_verification_code("13900000005", None).await),
+            Some((ErrorKind::UpstreamError, ErrorCode::UpstreamError))
+        );
+        for _ in 0..2 {
+            assert_eq!(
+                code_of(
+                    service.send_verification_code("13900000005", None).await
+                ),
+                None
+            );
+            service
+                .sms_send_cooldowns
+                .release("13900000005")
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            code_of(service.send_verification_code("13900000005", None).await),
+            Some((ErrorKind::RateLimited, ErrorCode::SmsSendLimitExceeded))
+        );
+        assert_eq!(sms_client.script.calls_to("send_code").len(), 6);
+        assert_eq!(
+            service
+                .sms_sends_per_phone
+                .count("13900000005")
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(service.sms_sends_per_ip.count("ip").await.unwrap(), 2);
+
+        // Test phones get no code and log in with the fixed one, as often
+        // as needed.
+        for _ in 0..3 {
+            let result = service
+                .send_verification_code("10000000000", Some("ip"))
+                .await;
+            assert_eq!(code_of(result), None);
+            assert!(service
+                .login_by_sms_verification_code("10000000000", "654321", None)
+                .await
+                .is_ok());
+        }
+        assert_eq!(sms_client.script.calls_to("send_code").len(), 6);
+        assert!(sms_client.script.calls_to("verify_code").is_empty());
+    }
+
+    #[actix_web::test]
+    async fn failed_logins_lock_out_the_phone_and_ip() {
+        let auth_client = FakeAuthClient::new()
+            .with_user("1", "139", "pw")
+            .with_user("2", "138", "pw");
+        let service = service(
+            auth_client.clone(),
+            FakeSMSVerificationCodeClient::new(),
+            FakeDogClient::new(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        let login = |phone, password, ip| {
+            let service = service.clone();
+            async move {
+                service
+                    .login_by_password(phone, password, ip)
+                    .await
+                    .err()
+                    .map(|e| e.code)
+            }
+        };
+        // A success clears the failures of the phone.
+        assert!(login("139", "bad", None).await.is_some());
+        assert_eq!(login("139", "pw", None).await, None);
+        for _ in 0..3 {
+            assert_eq!(
+                login("139", "bad", Some("ip1")).await,
+                Some(ErrorCode::Unauthorized)
+            );
         }
+        assert_eq!(
+            login("139", "pw", Some("ip2")).await,
+            Some(ErrorCode::LoginLocked)
+        );
+        assert_eq!(auth_client.script.calls_to("login").len(), 5);
+
+        // Two more failures from ip1 lock it for every phone.
+        for _ in 0..2 {
+            assert!(login("138", "bad", Some("ip1")).await.is_some());
+        }
+        assert_eq!(
+            login("138", "pw", Some("ip1")).await,
+            Some(ErrorCode::LoginLocked)
+        );
+        assert_eq!(login("138", "pw", Some("ip2")).await, None);
+    }
+
+    #[actix_web::test]
+    async fn concurrent_failed_logins_cannot_pass_the_limit() {
+        let auth_client = FakeAuthClient::new().with_user("1", "139", "pw");
+        auth_client.script.set_latency(Duration::from_millis(50));
+        let service = service(
+            auth_client.clone(),
+            FakeSMSVerificationCodeClient::new(),
+            FakeDogClient::new(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        let codes = futures::future::join_all((0..10).map(|i| {
+            let service = service.clone();
+            let password = format!("guess{}", i);
+            async move {
+                service
+                    .login_by_password("139", &password, None)
+                    .await
+                    .err()
+                    .map(|e| e.code)
+            }
+        }))
+        .await;
+        let unauthorized = codes
+            .iter()
+            .filter(|code| **code == Some(ErrorCode::Unauthorized))
+            .count();
+        assert_eq!(unauthorized, 3);
+        assert_eq!(auth_client.script.calls_to("login").len(), 3);
+        assert_eq!(
+            service
+                .login_by_password("139", "pw", None)
+                .await
+                .err()
+                .map(|e| e.code),
+            Some(ErrorCode::LoginLocked)
+        );
+    }
+
+    #[actix_web::test]
+    async fn sms_login_signs_up_unknown_phone_when_enabled() {
+        let auth_client = FakeAuthClient::new();
+        let sms_client =
+            FakeSMSVerificationCodeClient::new().with_code("139", "123456");
+        let disabled = service(
+            auth_client.clone(),
+            sms_client.clone(),
+            FakeDogClient::new(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        let err = disabled
+            .login_by_sms_verification_code("139", "123456", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::NotFound);
+        let enabled = service(
+            auth_client.clone(),
+            sms_client,
+            FakeDogClient::new(),
+            FakeWalkRequestClient::new(),
+            true,
+        );
+        let token = enabled
+            .login_by_sms_verification_code("139", "123456", None)
+            .await
+            .unwrap();
+        assert!(!stream_to_bytes(token).await.unwrap().is_empty());
+        assert!(auth_client.has_user("139"));
+        let signup = auth_client.script.calls_to("signup").remove(0);
+        assert_eq!(signup.args[..2], ["139", "123456"]);
+    }
+
+    #[actix_web::test]
+    async fn sms_codes_are_checked_before_users_and_kept_on_failure() {
+        let auth_client = FakeAuthClient::new().with_user("1", "139", "pw");
+        let service = service(
+            auth_client.clone(),
+            FakeSMSVerificationCodeClient::new()
+                .with_code("139", "123456")
+                .with_code("138", "123456"),
+            FakeDogClient::new(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        // Without a valid code unknown phones look like known ones.
+        let err = service
+            .login_by_sms_verification_code("137", "000000", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::SmsCodeInvalid);
+        assert!(auth_client.script.calls_to("exists_user").is_empty());
+        let err = service
+            .login_by_sms_verification_code("138", "123456", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code, ErrorCode::UserNotFound);
+
+        // A code is not used up by a login failing to issue a token.
+        auth_client.script.fail_next(
+            "generate_token",
+            Error::new(ErrorKind::UpstreamUnavailable, "down"),
+        );
+        let err = service
+            .login_by_sms_verification_code("139", "123456", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::UpstreamUnavailable);
+        assert!(service
+            .login_by_sms_verification_code("139", "123456", None)
+            .await
+            .is_ok());
+    }
+
+    #[actix_web::test]
+    async fn dogs_and_breeds_are_served_by_the_dog_client() {
+        let cat = Breed {
+            category: "cat".to_owned(),
+            ..dog("", "").breed
+        };
+        let dog_client = FakeDogClient::new()
+            .with_dog(dog("d1", "owner"))
+            .with_dog(dog("d2", "stranger"))
+            .with_breed(dog("", "").breed)
+            .with_breed(cat);
+        let service = service(
+            FakeAuthClient::new(),
+            FakeSMSVerificationCodeClient::new(),
+            dog_client.clone(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        let body = service
+            .add_dog("owner", json_stream(&json!({ "name": "不二" })))
+            .await
+            .unwrap();
+        assert_eq!(stream_to_bytes(body).await.unwrap(), r#"{"id":"dog-1"}"#);
+        assert_eq!(
+            dog_client.added(),
+            [("owner".to_owned(), json!({ "name": "不二" }))]
+        );
+        let body = service.dogs_of_owner("owner").await.unwrap();
+        let dogs: Vec<Dog> =
+            serde_json::from_slice(&stream_to_bytes(body).await.unwrap())
+                .unwrap();
+        assert_eq!(dogs.len(), 1);
+        assert_eq!(dogs[0].id, "d1");
+        let body = service.dog_breeds("cat").await.unwrap();
+        let breeds: Vec<Breed> =
+            serde_json::from_slice(&stream_to_bytes(body).await.unwrap())
+                .unwrap();
+        assert_eq!(breeds.len(), 1);
+        assert_eq!(breeds[0].category, "cat");
+        dog_client.script.fail_next(
+            "query_breeds",
+            Error::new(ErrorKind::UpstreamUnavailable, "down"),
+        );
+        let err = service.dog_breeds("cat").await.err().unwrap();
+        assert_eq!(err.kind, ErrorKind::UpstreamUnavailable);
+    }
+
+    #[actix_web::test]
+    async fn update_dog_requires_ownership() {
+        let dog_client = FakeDogClient::new().with_dog(dog("d1", "owner"));
+        let service = service(
+            FakeAuthClient::new(),
+            FakeSMSVerificationCodeClient::new(),
+            dog_client.clone(),
+            FakeWalkRequestClient::new(),
+            false,
+        );
+        let err = service
+            .update_dog("stranger", "d1", Bytes::from_static(b"{}"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::Authorization);
+        assert!(dog_client.script.calls_to("update_dog").is_empty());
+        let body = service
+            .update_dog("owner", "d1", Bytes::from_static(b"{}"))
+            .await
+            .unwrap();
+        assert_eq!(stream_to_bytes(body).await.unwrap(), "{}");
+        assert_eq!(dog_client.script.calls_to("update_dog").len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn nearby_requests_are_filled_with_dogs() {
+        let service = service(
+            FakeAuthClient::new(),
+            FakeSMSVerificationCodeClient::new(),
+            FakeDogClient::new()
+                .with_dog(dog("d1", "owner"))
+                .with_dog(dog("d2", "owner")),
+            FakeWalkRequestClient::new()
+                .with_walk_request(RawWalkRequest {
+                    id: "near".to_owned(),
+                    dog_ids: vec!["d1".to_owned(), "d2".to_owned()],
+                    latitude: 36.651,
+                    longitude: 117.12,
+                    ..Default::default()
+                })
+                .with_walk_request(RawWalkRequest {
+                    id: "far".to_owned(),
+                    dog_ids: vec!["d1".to_owned()],
+                    latitude: 39.9,
+                    longitude: 116.4,
+                    ..Default::default()
+                }),
+            false,
+        );
+        let requests = service
+            .nearby_requests(
+                117.12,
+                36.65,
+                None,
+                Pagination { page: 1, size: 10 },
+            )
+            .await
+            .unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].id, "near");
+        assert_eq!(requests[0].dogs.len(), 2);
     }
 }
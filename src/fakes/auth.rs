@@ -0,0 +1,130 @@
+use super::{json_stream, Script};
+use crate::core::{
+    clients::auth::AuthClient,
+    error::{Error, ErrorKind},
+    service::ByteStream,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct State {
+    // phone -> (user id, password)
+    users: HashMap<String, (String, Option<String>)>,
+    // token -> user id
+    tokens: HashMap<String, String>,
+}
+
+#[derive(Clone, Default)]
+pub struct FakeAuthClient {
+    pub script: Script,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeAuthClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_user(self, id: &str, phone: &str, password: &str) -> Self {
+        self.state.lock().unwrap().users.insert(
+            phone.to_owned(),
+            (id.to_owned(), Some(password.to_owned())),
+        );
+        self
+    }
+
+    pub fn with_token(self, token: &str, user_id: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .tokens
+            .insert(token.to_owned(), user_id.to_owned());
+        self
+    }
+
+    pub fn has_user(&self, phone: &str) -> bool {
+        self.state.lock().unwrap().users.contains_key(phone)
+    }
+
+    fn insert_user(&self, phone: &str, password: Option<&str>) -> String {
+        let mut state = self.state.lock().unwrap();
+        let id = format!("user-{}", state.users.len() + 1);
+        state.users.insert(
+            phone.to_owned(),
+            (id.clone(), password.map(|p| p.to_owned())),
+        );
+        id
+    }
+
+    fn issue_token(&self, phone: &str) -> Result<ByteStream, Error> {
+        let mut state = self.state.lock().unwrap();
+        let (id, _) = state
+            .users
+            .get(phone)
+            .cloned()
+            .ok_or(Error::new(ErrorKind::NotFound, "user not exists"))?;
+        let token = format!("token-{}-{}", id, state.tokens.len() + 1);
+        state.tokens.insert(token.clone(), id);
+        Ok(json_stream(&json!({ "token": token })))
+    }
+}
+
+impl AuthClient for FakeAuthClient {
+    async fn signup(
+        &self,
+        phone: &str,
+        verification_code: &str,
+        password: &str,
+    ) -> Result<ByteStream, Error> {
+        self.script
+            .enter("signup", &[phone, verification_code, password])
+            .await?;
+        if self.has_user(phone) {
+            return Err(Error::new(ErrorKind::Conflict, "user already exists"));
+        }
+        self.insert_user(phone, Some(password));
+        self.issue_token(phone)
+    }
+
+    async fn login(
+        &self,
+        phone: &str,
+        password: &str,
+    ) -> Result<ByteStream, Error> {
+        self.script.enter("login", &[phone, password]).await?;
+        let matched = matches!(
+            self.state.lock().unwrap().users.get(phone),
+            Some((_, Some(p))) if p == password
+        );
+        if !matched {
+            return Err(Error::new(
+                ErrorKind::Authentication,
+                "invalid phone or password",
+            ));
+        }
+        self.issue_token(phone)
+    }
+
+    async fn verify_token(&self, token: &str) -> Result<String, Error> {
+        self.script.enter("verify_token", &[token]).await?;
+        self.state
+            .lock()
+            .unwrap()
+            .tokens
+            .get(token)
+            .cloned()
+            .ok_or(Error::new(ErrorKind::Authentication, "invalid token"))
+    }
+
+    async fn exists_user(&self, phone: &str) -> Result<bool, Error> {
+        self.script.enter("exists_user", &[phone]).await?;
+        Ok(self.has_user(phone))
+    }
+
+    async fn generate_token(&self, phone: &str) -> Result<ByteStream, Error> {
+        self.script.enter("generate_token", &[phone]).await?;
+        self.issue_token(phone)
+    }
+}
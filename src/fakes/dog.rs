@@ -0,0 +1,168 @@
+use super::{json_stream, Script};
+use crate::core::{
+    clients::dog::{Breed, Dog, DogClient},
+    error::{Error, ErrorKind},
+    requests::DogQuery,
+    service::ByteStream,
+};
+use crate::utils::io::stream_to_bytes;
+use bytes::Bytes;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct State {
+    dogs: Vec<Dog>,
+    breeds: Vec<Breed>,
+    // owner id and body of every dog added through the client
+    added: Vec<(String, Value)>,
+}
+
+#[derive(Clone, Default)]
+pub struct FakeDogClient {
+    pub script: Script,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeDogClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_dog(self, dog: Dog) -> Self {
+        self.state.lock().unwrap().dogs.push(dog);
+        self
+    }
+
+    pub fn with_breed(self, breed: Breed) -> Self {
+        self.state.lock().unwrap().breeds.push(breed);
+        self
+    }
+
+    pub fn dogs(&self) -> Vec<Dog> {
+        self.state.lock().unwrap().dogs.clone()
+    }
+
+    pub fn added(&self) -> Vec<(String, Value)> {
+        self.state.lock().unwrap().added.clone()
+    }
+
+    fn find(&self, query: &DogQuery) -> Vec<Dog> {
+        let dogs: Vec<Dog> = self
+            .dogs()
+            .into_iter()
+            .filter(|d| query.id.as_ref().is_none_or(|id| &d.id == id))
+            .filter(|d| {
+                query.id_in.as_ref().is_none_or(|ids| ids.contains(&d.id))
+            })
+            .filter(|d| {
+                query
+                    .owner_id
+                    .as_ref()
+                    .is_none_or(|owner_id| &d.owner_id == owner_id)
+            })
+            .collect();
+        match &query.pagination {
+            Some(p) => dogs
+                .into_iter()
+                .skip(((p.page.max(1) - 1) * p.size) as usize)
+                .take(p.size as usize)
+                .collect(),
+            None => dogs,
+        }
+    }
+}
+
+impl DogClient for FakeDogClient {
+    async fn add_dog(
+        &self,
+        owner_id: &str,
+        body: ByteStream,
+    ) -> Result<ByteStream, Error> {
+        self.script.enter("add_dog", &[owner_id]).await?;
+        let bs = stream_to_bytes(body).await?;
+        let value: Value =
+            serde_json::from_slice(&bs).map_err(Error::invalid_body)?;
+        let mut state = self.state.lock().unwrap();
+        state.added.push((owner_id.to_owned(), value));
+        let id = format!("dog-{}", state.added.len());
+        Ok(json_stream(&json!({ "id": id })))
+    }
+
+    async fn dogs_by_owner_id(
+        &self,
+        owner_id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.script.enter("dogs_by_owner_id", &[owner_id]).await?;
+        Ok(json_stream(&self.find(&DogQuery {
+            owner_id: Some(owner_id.to_owned()),
+            ..Default::default()
+        })))
+    }
+
+    async fn query_dogs(&self, query: &DogQuery) -> Result<Vec<Dog>, Error> {
+        self.script
+            .enter("query_dogs", &[&format!("{:?}", query)])
+            .await?;
+        Ok(self.find(query))
+    }
+
+    async fn is_owner_of_the_dog(
+        &self,
+        owner_id: &str,
+        dog_id: &str,
+    ) -> Result<bool, Error> {
+        self.script
+            .enter("is_owner_of_the_dog", &[owner_id, dog_id])
+            .await?;
+        Ok(self
+            .dogs()
+            .iter()
+            .any(|d| d.id == dog_id && d.owner_id == owner_id))
+    }
+
+    async fn update_dog_portrait(
+        &self,
+        dog_id: &str,
+        portrait_id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.script
+            .enter("update_dog_portrait", &[dog_id, portrait_id])
+            .await?;
+        let mut state = self.state.lock().unwrap();
+        let dog = state
+            .dogs
+            .iter_mut()
+            .find(|d| d.id == dog_id)
+            .ok_or(Error::new(ErrorKind::NotFound, "dog not found"))?;
+        dog.portrait_id = Some(portrait_id.to_owned());
+        Ok(json_stream(dog))
+    }
+
+    async fn query_breeds(&self, category: &str) -> Result<ByteStream, Error> {
+        self.script.enter("query_breeds", &[category]).await?;
+        let breeds: Vec<Breed> = self
+            .state
+            .lock()
+            .unwrap()
+            .breeds
+            .iter()
+            .filter(|b| b.category == category)
+            .cloned()
+            .collect();
+        Ok(json_stream(&breeds))
+    }
+
+    async fn update_dog(
+        &self,
+        dog_id: &str,
+        body: Bytes,
+    ) -> Result<ByteStream, Error> {
+        let body = String::from_utf8_lossy(&body).into_owned();
+        self.script.enter("update_dog", &[dog_id, &body]).await?;
+        if !self.dogs().iter().any(|d| d.id == dog_id) {
+            return Err(Error::new(ErrorKind::NotFound, "dog not found"));
+        }
+        Ok(Box::pin(futures::stream::iter(vec![Ok(Bytes::from(body))])))
+    }
+}
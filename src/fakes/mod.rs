@@ -0,0 +1,114 @@
+//! In-memory implementations of the client traits so that the service and
+//! the middlewares can be exercised without the real services.
+//!
+//! Every fake shares a [`Script`] which records the calls it receives and
+//! lets tests inject failures and latency per method.
+//!
+//! Other crates get them with the `fakes` feature.
+
+pub mod auth;
+pub mod dog;
+pub mod sms_verification_code;
+pub mod upload;
+pub mod walk_request;
+
+use crate::core::{error::Error, service::ByteStream};
+use bytes::Bytes;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub method: &'static str,
+    pub args: Vec<String>,
+}
+
+#[derive(Default)]
+struct ScriptState {
+    latency: Duration,
+    next_failures: HashMap<&'static str, VecDeque<Error>>,
+    failures: HashMap<&'static str, Error>,
+    calls: Vec<Call>,
+}
+
+#[derive(Clone, Default)]
+pub struct Script {
+    state: Arc<Mutex<ScriptState>>,
+}
+
+impl Script {
+    /// Delays every call by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Fails the next call to `method` with `error`, queued failures are
+    /// consumed in order.
+    pub fn fail_next(&self, method: &'static str, error: Error) {
+        self.state
+            .lock()
+            .unwrap()
+            .next_failures
+            .entry(method)
+            .or_default()
+            .push_back(error);
+    }
+
+    /// Fails every call to `method` with `error` until the failures are
+    /// cleared.
+    pub fn fail_always(&self, method: &'static str, error: Error) {
+        self.state.lock().unwrap().failures.insert(method, error);
+    }
+
+    pub fn clear_failures(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.next_failures.clear();
+        state.failures.clear();
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    pub fn calls_to(&self, method: &str) -> Vec<Call> {
+        self.calls()
+            .into_iter()
+            .filter(|c| c.method == method)
+            .collect()
+    }
+
+    /// Records the call, waits for the configured latency and returns the
+    /// injected failure if there is one.
+    pub(crate) async fn enter(
+        &self,
+        method: &'static str,
+        args: &[&str],
+    ) -> Result<(), Error> {
+        let (latency, failure) = {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(Call {
+                method,
+                args: args.iter().map(|a| a.to_string()).collect(),
+            });
+            let failure = match state.next_failures.get_mut(method) {
+                Some(queue) if !queue.is_empty() => queue.pop_front(),
+                _ => state.failures.get(method).cloned(),
+            };
+            (state.latency, failure)
+        };
+        if !latency.is_zero() {
+            actix_web::rt::time::sleep(latency).await;
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn json_stream<T: Serialize>(value: &T) -> ByteStream {
+    let bytes = Bytes::from(serde_json::to_vec(value).unwrap());
+    Box::pin(futures::stream::iter(vec![Ok(bytes)]))
+}
@@ -0,0 +1,57 @@
+use super::{json_stream, Script};
+use crate::core::{
+    clients::sms_verification_code::SMSVerificationCodeClient, error::Error,
+    service::ByteStream,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Code sent by [`FakeSMSVerificationCodeClient::send_code`] unless another
+/// one is seeded for the phone.
+pub const DEFAULT_CODE: &str = "123456";
+
+#[derive(Clone, Default)]
+pub struct FakeSMSVerificationCodeClient {
+    pub script: Script,
+    codes: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl FakeSMSVerificationCodeClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_code(self, phone: &str, code: &str) -> Self {
+        self.codes
+            .lock()
+            .unwrap()
+            .insert(phone.to_owned(), code.to_owned());
+        self
+    }
+
+    pub fn code_of(&self, phone: &str) -> Option<String> {
+        self.codes.lock().unwrap().get(phone).cloned()
+    }
+}
+
+impl SMSVerificationCodeClient for FakeSMSVerificationCodeClient {
+    async fn send_code(&self, phone: &str) -> Result<ByteStream, Error> {
+        self.script.enter("send_code", &[phone]).await?;
+        self.codes
+            .lock()
+            .unwrap()
+            .entry(phone.to_owned())
+            .or_insert(DEFAULT_CODE.to_owned());
+        Ok(json_stream(&json!({ "phone": phone })))
+    }
+
+    async fn verify_code(
+        &self,
+        phone: &str,
+        code: &str,
+    ) -> Result<bool, Error> {
+        self.script.enter("verify_code", &[phone, code]).await?;
+        Ok(self.code_of(phone).as_deref() == Some(code))
+    }
+}
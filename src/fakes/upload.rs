@@ -0,0 +1,99 @@
+use super::{json_stream, Script};
+use crate::core::{
+    clients::upload::{Download, UploadClient},
+    error::{Error, ErrorKind},
+    service::ByteStream,
+};
+use bytes::Bytes;
+use futures::TryStreamExt;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub content_type: String,
+    pub user_id: String,
+    pub content: Bytes,
+}
+
+#[derive(Clone, Default)]
+pub struct FakeUploadClient {
+    pub script: Script,
+    files: Arc<Mutex<HashMap<String, StoredFile>>>,
+}
+
+impl FakeUploadClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(
+        self,
+        id: &str,
+        content_type: &str,
+        content: &[u8],
+    ) -> Self {
+        self.files.lock().unwrap().insert(
+            id.to_owned(),
+            StoredFile {
+                content_type: content_type.to_owned(),
+                user_id: String::new(),
+                content: Bytes::copy_from_slice(content),
+            },
+        );
+        self
+    }
+
+    pub fn file(&self, id: &str) -> Option<StoredFile> {
+        self.files.lock().unwrap().get(id).cloned()
+    }
+}
+
+impl UploadClient for FakeUploadClient {
+    async fn upload(
+        &self,
+        content_type_header: &str,
+        user_id: &str,
+        limit: usize,
+        payload: ByteStream,
+    ) -> Result<ByteStream, Error> {
+        self.script
+            .enter("upload", &[content_type_header, user_id])
+            .await?;
+        let mut content = Vec::new();
+        let mut payload = payload;
+        while let Some(chunk) = payload.try_next().await? {
+            content.extend_from_slice(&chunk);
+            if content.len() > limit {
+                return Err(Error::new(
+                    ErrorKind::PayloadTooLarge,
+                    format!("payload exceeds the limit of {} bytes", limit),
+                ));
+            }
+        }
+        let mut files = self.files.lock().unwrap();
+        let id = format!("upload-{}", files.len() + 1);
+        files.insert(
+            id.clone(),
+            StoredFile {
+                content_type: content_type_header.to_owned(),
+                user_id: user_id.to_owned(),
+                content: content.into(),
+            },
+        );
+        Ok(json_stream(&json!({ "id": id })))
+    }
+
+    async fn download(&self, id: &str) -> Result<Download, Error> {
+        self.script.enter("download", &[id]).await?;
+        let file = self
+            .file(id)
+            .ok_or(Error::new(ErrorKind::NotFound, "file not found"))?;
+        Ok(Download {
+            content_type: Some(file.content_type),
+            content_length: Some(file.content.len() as u64),
+            body: Box::pin(futures::stream::iter(vec![Ok(file.content)])),
+        })
+    }
+}
@@ -0,0 +1,231 @@
+use super::{json_stream, Script};
+use crate::core::{
+    clients::walk_request::{
+        WalkRequest, WalkRequestClient, WalkRequestCreate, WalkRequestQuery,
+    },
+    error::{Error, ErrorKind},
+    service::ByteStream,
+};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+fn distance_in_meters(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b.1 - a.1).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * h.sqrt().asin()
+}
+
+#[derive(Default)]
+struct State {
+    requests: Vec<WalkRequest>,
+    // walk request id -> users who accepted it
+    acceptances: HashMap<String, Vec<String>>,
+}
+
+#[derive(Clone, Default)]
+pub struct FakeWalkRequestClient {
+    pub script: Script,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeWalkRequestClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_walk_request(self, request: WalkRequest) -> Self {
+        self.state.lock().unwrap().requests.push(request);
+        self
+    }
+
+    pub fn walk_requests(&self) -> Vec<WalkRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    fn transit(
+        &self,
+        user_id: &str,
+        id: &str,
+        apply: impl FnOnce(&mut WalkRequest, &str),
+    ) -> Result<ByteStream, Error> {
+        let mut state = self.state.lock().unwrap();
+        let request =
+            state.requests.iter_mut().find(|r| r.id == id).ok_or(
+                Error::new(ErrorKind::NotFound, "walk request not found"),
+            )?;
+        apply(request, user_id);
+        request.updated_at = Some(Utc::now());
+        Ok(json_stream(request))
+    }
+}
+
+impl WalkRequestClient for FakeWalkRequestClient {
+    async fn query_walk_requests(
+        &self,
+        query: WalkRequestQuery,
+    ) -> Result<Vec<WalkRequest>, Error> {
+        self.script
+            .enter("query_walk_requests", &[&format!("{:?}", query)])
+            .await?;
+        let state = self.state.lock().unwrap();
+        let accepted_by =
+            |id: &str| state.acceptances.get(id).cloned().unwrap_or_default();
+        let mut requests: Vec<WalkRequest> = state
+            .requests
+            .iter()
+            .filter(|r| query.id.as_ref().is_none_or(|id| &r.id == id))
+            .filter(|r| {
+                query
+                    .dog_ids_includes_all
+                    .as_ref()
+                    .is_none_or(|ids| ids.iter().all(|i| r.dog_ids.contains(i)))
+            })
+            .filter(|r| {
+                query
+                    .dog_ids_includes_any
+                    .as_ref()
+                    .is_none_or(|ids| ids.iter().any(|i| r.dog_ids.contains(i)))
+            })
+            .filter(|r| {
+                query
+                    .accepted_by
+                    .as_ref()
+                    .is_none_or(|u| r.accepted_by.as_ref() == Some(u))
+            })
+            .filter(|r| {
+                query
+                    .accepted_by_neq
+                    .as_ref()
+                    .is_none_or(|u| r.accepted_by.as_ref() != Some(u))
+            })
+            .filter(|r| !query.accepted_by_is_null || r.accepted_by.is_none())
+            .filter(|r| {
+                query.acceptances_includes_all.as_ref().is_none_or(|us| {
+                    let accepted = accepted_by(&r.id);
+                    us.iter().all(|u| accepted.contains(u))
+                })
+            })
+            .filter(|r| {
+                query.acceptances_includes_any.as_ref().is_none_or(|us| {
+                    let accepted = accepted_by(&r.id);
+                    us.iter().any(|u| accepted.contains(u))
+                })
+            })
+            .cloned()
+            .filter_map(|mut r| match &query.nearby {
+                Some(nearby) => {
+                    let distance = distance_in_meters(
+                        (nearby.latitude, nearby.longitude),
+                        (r.latitude, r.longitude),
+                    );
+                    r.distance = Some(distance);
+                    (distance <= nearby.radius).then_some(r)
+                }
+                None => Some(r),
+            })
+            .collect();
+        if let Some(p) = &query.pagination {
+            requests = requests
+                .into_iter()
+                .skip(((p.page.max(1) - 1) * p.size) as usize)
+                .take(p.size as usize)
+                .collect();
+        }
+        Ok(requests)
+    }
+
+    async fn create_walk_request(
+        &self,
+        user_id: &str,
+        create: &WalkRequestCreate,
+    ) -> Result<ByteStream, Error> {
+        self.script
+            .enter("create_walk_request", &[user_id, &format!("{:?}", create)])
+            .await?;
+        let mut state = self.state.lock().unwrap();
+        let now = Utc::now();
+        let request = WalkRequest {
+            id: format!("walk-request-{}", state.requests.len() + 1),
+            dog_ids: create.dog_ids.clone(),
+            latitude: create.latitude,
+            longitude: create.longitude,
+            status: "Waiting".to_owned(),
+            created_at: Some(now),
+            updated_at: Some(now),
+            ..Default::default()
+        };
+        let stream = json_stream(&request);
+        state.requests.push(request);
+        Ok(stream)
+    }
+
+    async fn accept_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.script
+            .enter("accept_walk_request", &[user_id, id])
+            .await?;
+        self.state
+            .lock()
+            .unwrap()
+            .acceptances
+            .entry(id.to_owned())
+            .or_default()
+            .push(user_id.to_owned());
+        self.transit(user_id, id, |r, user_id| {
+            r.accepted_by = Some(user_id.to_owned());
+            r.accepted_at = Some(Utc::now());
+            r.status = "Accepted".to_owned();
+        })
+    }
+
+    async fn cancel_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.script
+            .enter("cancel_walk_request", &[user_id, id])
+            .await?;
+        self.transit(user_id, id, |r, _| {
+            r.canceled_at = Some(Utc::now());
+            r.status = "Canceled".to_owned();
+        })
+    }
+
+    async fn start_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.script
+            .enter("start_walk_request", &[user_id, id])
+            .await?;
+        self.transit(user_id, id, |r, _| {
+            r.started_at = Some(Utc::now());
+            r.status = "Started".to_owned();
+        })
+    }
+
+    async fn finish_walk_request(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> Result<ByteStream, Error> {
+        self.script
+            .enter("finish_walk_request", &[user_id, id])
+            .await?;
+        self.transit(user_id, id, |r, _| {
+            r.finished_at = Some(Utc::now());
+            r.status = "Finished".to_owned();
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+
+use crate::{
+    core::{
+        clients::{
+            auth::AuthClient, dog::DogClient,
+            sms_verification_code::SMSVerificationCodeClient,
+            upload::UploadClient, walk_request::WalkRequestClient,
+        },
+        error::Error,
+        service::Service,
+    },
+    utils::{network, restful::Query},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct LoginBySMSVerificationCodeParams {
+    pub phone: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginByPasswordParams {
+    pub phone: String,
+    pub password: String,
+}
+
+fn client_ip(req: &HttpRequest) -> Option<String> {
+    network::client_ip(req).map(|ip| ip.to_string())
+}
+
+pub(crate) async fn login_by_password<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    Json(params): Json<LoginByPasswordParams>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service
+        .login_by_password(
+            &params.phone,
+            &params.password,
+            client_ip(&req).as_deref(),
+        )
+        .await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
+
+pub(crate) async fn login_by_sms_verification_code<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    Query(params): Query<LoginBySMSVerificationCodeParams>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service
+        .login_by_sms_verification_code(
+            &params.phone,
+            &params.code,
+            client_ip(&req).as_deref(),
+        )
+        .await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
+
+pub(crate) async fn send_verification_code<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    phone: Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service
+        .send_verification_code(&phone, client_ip(&req).as_deref())
+        .await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
@@ -0,0 +1,200 @@
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::core::error::{Error, ErrorCode, ErrorKind};
+use crate::utils::circuit_breaker::CircuitState;
+use crate::utils::health::{HealthChecker, UpstreamStatus};
+use crate::utils::network::{parse_network, IpBlocklist};
+use crate::utils::upstream::{Upstream, Upstreams};
+
+/// A route served by the gateway, `upstreams` are the services it calls.
+#[derive(Debug, Serialize)]
+pub struct RouteInfo {
+    /// `*` for any method.
+    pub method: &'static str,
+    /// Paths ending with `/*` match everything below.
+    pub path: &'static str,
+    pub authenticated: bool,
+    pub upstreams: &'static [&'static str],
+    pub handler: RouteHandler,
+}
+
+/// What serves a route, the first upstream of the route is the one passed
+/// through to.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteHandler {
+    Metrics,
+    Healthz,
+    Readyz,
+    LoginByPassword,
+    LoginBySmsVerificationCode,
+    SendVerificationCode,
+    PassThrough,
+    CreateDog,
+    UpdateDog,
+    UpdateDogPortrait,
+    CreateWalkRequest,
+    NearbyWalkRequests,
+    AcceptWalkRequest,
+    CancelWalkRequest,
+    StartWalkRequest,
+    FinishWalkRequest,
+    Upload,
+    Download,
+}
+
+/// What the admin API inspects and controls.
+pub struct AdminState {
+    pub routes: &'static [RouteInfo],
+    pub upstreams: Upstreams,
+    /// The effective config with secrets masked.
+    pub config: Value,
+    pub ip_blocklist: IpBlocklist,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlockIpParams {
+    /// In CIDR notation or a bare address.
+    pub network: String,
+    /// Blocked until unblocked if missing.
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct UpstreamView {
+    name: &'static str,
+    address: String,
+    drained: bool,
+    circuit: CircuitState,
+    consecutive_failures: u32,
+    /// As of the last readiness check, if any.
+    health: Option<UpstreamStatus>,
+}
+
+async fn view(upstream: &Upstream, checker: &HealthChecker) -> UpstreamView {
+    let (circuit, consecutive_failures) = upstream.circuit();
+    let health = checker.cached().await.and_then(|readiness| {
+        readiness.upstreams.get(upstream.name).map(|h| h.status)
+    });
+    UpstreamView {
+        name: upstream.name,
+        address: upstream.address.clone(),
+        drained: upstream.is_drained(),
+        circuit,
+        consecutive_failures,
+        health,
+    }
+}
+
+fn find<'a>(state: &'a AdminState, name: &str) -> Result<&'a Upstream, Error> {
+    state.upstreams.find(name).ok_or_else(|| {
+        Error::new(ErrorKind::NotFound, format!("upstream {} not found", name))
+    })
+}
+
+pub async fn routes(state: Data<AdminState>) -> HttpResponse {
+    HttpResponse::Ok().json(state.routes)
+}
+
+pub async fn config(state: Data<AdminState>) -> HttpResponse {
+    HttpResponse::Ok().json(&state.config)
+}
+
+pub async fn upstreams(
+    state: Data<AdminState>,
+    checker: Data<HealthChecker>,
+) -> HttpResponse {
+    let mut views = Vec::new();
+    for upstream in state.upstreams.all() {
+        views.push(view(upstream, &checker).await);
+    }
+    HttpResponse::Ok().json(views)
+}
+
+pub async fn caches(checker: Data<HealthChecker>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "readiness": checker.cache_stats().await }))
+}
+
+pub async fn flush_caches(checker: Data<HealthChecker>) -> HttpResponse {
+    checker.flush().await;
+    HttpResponse::Ok().json(json!({ "readiness": checker.cache_stats().await }))
+}
+
+pub async fn drain(
+    state: Data<AdminState>,
+    checker: Data<HealthChecker>,
+    name: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let upstream = find(&state, &name)?;
+    upstream.set_drained(true);
+    Ok(HttpResponse::Ok().json(view(upstream, &checker).await))
+}
+
+pub async fn undrain(
+    state: Data<AdminState>,
+    checker: Data<HealthChecker>,
+    name: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let upstream = find(&state, &name)?;
+    upstream.set_drained(false);
+    Ok(HttpResponse::Ok().json(view(upstream, &checker).await))
+}
+
+pub async fn open_circuit(
+    state: Data<AdminState>,
+    checker: Data<HealthChecker>,
+    name: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let upstream = find(&state, &name)?;
+    upstream.force_open_circuit();
+    Ok(HttpResponse::Ok().json(view(upstream, &checker).await))
+}
+
+pub async fn reset_circuit(
+    state: Data<AdminState>,
+    checker: Data<HealthChecker>,
+    name: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let upstream = find(&state, &name)?;
+    upstream.reset_circuit();
+    Ok(HttpResponse::Ok().json(view(upstream, &checker).await))
+}
+
+pub async fn ip_blocks(state: Data<AdminState>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(state.ip_blocklist.list().await?))
+}
+
+pub async fn block_ip(
+    state: Data<AdminState>,
+    Json(params): Json<BlockIpParams>,
+) -> Result<HttpResponse, Error> {
+    let network = parse_network(&params.network).map_err(|e| {
+        Error::new(ErrorKind::Validation, e).with_code(ErrorCode::InvalidBody)
+    })?;
+    state
+        .ip_blocklist
+        .block(network, params.ttl_secs.map(Duration::from_secs))
+        .await?;
+    Ok(HttpResponse::Ok().json(state.ip_blocklist.list().await?))
+}
+
+pub async fn unblock_ip(
+    state: Data<AdminState>,
+    network: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let network = parse_network(&network)
+        .map_err(|e| Error::new(ErrorKind::Validation, e))?;
+    if !state.ip_blocklist.unblock(network).await? {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} is not blocked", network),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(state.ip_blocklist.list().await?))
+}
@@ -2,14 +2,13 @@ use std::pin::Pin;
 
 use actix_web::{web::Bytes, Handler, HttpRequest, HttpResponse};
 use futures::Future;
-use http::StatusCode;
 use reqwest::{header::HeaderMap, Client, Method};
 use serde::Deserialize;
 use url::Url;
 
 use crate::{
-    core::error::Error,
-    utils::{io::stream_to_bytes, restful::request},
+    core::error::{Error, ErrorKind},
+    utils::{io::stream_to_bytes, restful::request, upstream::Upstream},
 };
 
 #[derive(Debug, Deserialize)]
@@ -23,13 +22,11 @@ fn parse_url(
     path: &str,
     query: &str,
 ) -> Result<Url, Error> {
-    let base_url =
-        Url::parse(&format!("http://{}", host_and_port)).map_err(|e| {
-            Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-        })?;
-    let mut url = base_url.join(path).map_err(|e| {
-        Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-    })?;
+    let base_url = Url::parse(&format!("http://{}", host_and_port))
+        .map_err(Error::wrap(ErrorKind::Internal))?;
+    let mut url = base_url
+        .join(path)
+        .map_err(Error::wrap(ErrorKind::Internal))?;
     if query.is_empty() {
         url.set_query(Some(query));
     }
@@ -37,7 +34,7 @@ fn parse_url(
 }
 
 pub(crate) fn pass_through<QP, RP, QF, RF>(
-    host_and_port: &str,
+    upstream: &Upstream,
     path: Option<&str>,
     request_body_processor: QP,
     response_processor: RP,
@@ -50,13 +47,13 @@ where
     QF: Future<Output = Result<Bytes, Error>> + 'static,
     RF: Future<Output = Result<Bytes, Error>> + 'static,
 {
-    let host_and_port = host_and_port.to_owned();
+    let upstream = upstream.clone();
     let path = path.map(|p| p.to_owned());
     move |req: HttpRequest,
           bytes: Bytes|
           -> Pin<Box<dyn Future<Output = Result<HttpResponse, Error>>>> {
         let response_processor = response_processor.clone();
-        let host_and_port = host_and_port.clone();
+        let upstream = upstream.clone();
         let path = if let Some(path) = path.clone() {
             path.clone()
         } else {
@@ -66,7 +63,7 @@ where
         for (name, value) in req.headers() {
             headers.insert(name, value.clone());
         }
-        match parse_url(&host_and_port, &path, req.query_string()) {
+        match parse_url(&upstream.address, &path, req.query_string()) {
             Ok(mut url) => {
                 url.set_query(if req.query_string() != "" {
                     Some(req.query_string())
@@ -75,9 +72,12 @@ where
                 });
                 let mut builder;
                 match req.method() {
-                    &Method::GET | &Method::OPTIONS | &Method::TRACE => {
+                    &Method::GET | &Method::TRACE => {
                         builder = Client::new().get(url)
                     }
+                    &Method::OPTIONS => {
+                        builder = Client::new().request(Method::OPTIONS, url)
+                    }
                     &Method::POST => builder = Client::new().post(url),
                     &Method::PUT => builder = Client::new().put(url),
                     &Method::DELETE => builder = Client::new().delete(url),
@@ -86,7 +86,7 @@ where
                     _ => {
                         return Box::pin(async {
                             Err(Error::new(
-                                StatusCode::METHOD_NOT_ALLOWED.as_u16(),
+                                ErrorKind::MethodNotAllowed,
                                 "unsupported method",
                             ))
                         })
@@ -97,15 +97,13 @@ where
                     let bytes = request_body_processor(&req, bytes).await?;
                     headers.insert("Content-Length", bytes.len().into());
                     builder = builder.headers(headers).body(bytes);
-                    let stream = request(builder).await?;
+                    let stream = request(&upstream, builder).await?;
                     let bytes = stream_to_bytes(stream).await?;
                     let res = response_processor(bytes).await?;
                     Ok(HttpResponse::Ok().body(res))
                 })
             }
-            Err(e) => Box::pin(async move {
-                Err(Error::new(StatusCode::BAD_REQUEST.as_u16(), e.to_string()))
-            }),
+            Err(e) => Box::pin(async move { Err(e) }),
         }
     }
 }
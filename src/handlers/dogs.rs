@@ -0,0 +1,54 @@
+use actix_web::{
+    web::{Bytes, Data, Json, Path},
+    HttpResponse,
+};
+
+use crate::{
+    core::{
+        clients::{
+            auth::AuthClient, dog::DogClient,
+            sms_verification_code::SMSVerificationCodeClient,
+            upload::UploadClient, walk_request::WalkRequestClient,
+        },
+        error::Error,
+        requests::DogPortraitUpdate,
+        service::Service,
+    },
+    utils::restful::UserID,
+};
+
+pub(crate) async fn update_dog<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    user_id: UserID,
+    dog_id: Path<String>,
+    body: Bytes,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service.update_dog(&user_id.0, &dog_id, body).await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
+
+pub(crate) async fn update_dog_portrait<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    user_id: UserID,
+    dog_id: Path<String>,
+    Json(update): Json<DogPortraitUpdate>,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service
+        .update_dog_portrait(&user_id.0, &dog_id, &update.portrait_id)
+        .await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
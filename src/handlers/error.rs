@@ -1,11 +1,89 @@
-use crate::core::error::Error;
-use actix_web::{body::BoxBody, HttpResponse, ResponseError};
+use crate::core::error::{Error, ErrorCode, ErrorKind};
+use crate::handlers::messages::{message, Locale};
+use crate::utils::redact::Redactor;
+use actix_web::{
+    http::{header::CONTENT_LANGUAGE, StatusCode},
+    HttpResponse, ResponseError,
+};
+use log::{log, Level};
+use std::future::{ready, Ready};
 
 impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.kind.status_code().as_u16())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
     fn error_response(
         &self,
     ) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
-        HttpResponse::build(self.status_code())
-            .body(BoxBody::new(self.cause.to_owned()))
+        render(self, &RenderOptions::default())
+    }
+}
+
+/// Per request settings of how errors are rendered.
+pub(crate) struct RenderOptions<'a> {
+    pub request_id: Option<&'a str>,
+    pub locale: Locale,
+    pub sanitize: bool,
+    pub redactor: &'a Redactor,
+}
+
+impl Default for RenderOptions<'_> {
+    fn default() -> Self {
+        Self {
+            request_id: None,
+            locale: Locale::ZhCn,
+            sanitize: true,
+            redactor: Redactor::builtin(),
+        }
     }
 }
+
+/// Renders `error` as the JSON error envelope, upstream errors are logged in
+/// full, secrets masked, since the client may only get a generic message.
+pub(crate) fn render(error: &Error, options: &RenderOptions) -> HttpResponse {
+    let request_id = options.request_id;
+    if let Some(upstream) = error.upstream {
+        let level = if error.kind.status_code().is_server_error() {
+            Level::Error
+        } else {
+            Level::Warn
+        };
+        log!(
+            level,
+            "upstream {} failed, request id: {}, error: {}",
+            upstream,
+            request_id.unwrap_or("-"),
+            options.redactor.redact(&error.to_string())
+        );
+    }
+    HttpResponse::build(error.status_code())
+        .insert_header((CONTENT_LANGUAGE, options.locale.tag()))
+        .json(error.envelope(
+            request_id,
+            options.sanitize,
+            message(error.code, options.locale),
+        ))
+}
+
+/// Renders any actix error, errors not raised by the gateway itself get the
+/// generic code of their status.
+pub(crate) fn render_actix_error(
+    error: &actix_web::Error,
+    options: &RenderOptions,
+) -> HttpResponse {
+    match error.as_error::<Error>() {
+        Some(e) => render(e, options),
+        None => {
+            let status_code = error.as_response_error().status_code();
+            let kind = ErrorKind::from_status(status_code.as_u16());
+            render(&Error::new(kind, error), options)
+        }
+    }
+}
+
+pub(crate) fn route_not_found() -> Ready<Result<HttpResponse, Error>> {
+    ready(Err(Error::new(ErrorKind::NotFound, "route not found")
+        .with_code(ErrorCode::RouteNotFound)))
+}
@@ -0,0 +1,21 @@
+use actix_web::{web::Data, HttpResponse};
+use serde_json::json;
+
+use crate::utils::health::{HealthChecker, ReadinessStatus};
+
+/// Answers as long as the process serves requests.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Reports the reachability of every upstream, the gateway is ready unless
+/// a critical one is down.
+pub async fn readyz(checker: Data<HealthChecker>) -> HttpResponse {
+    let readiness = checker.readiness().await;
+    match readiness.status {
+        ReadinessStatus::Ready => HttpResponse::Ok().json(readiness),
+        ReadinessStatus::NotReady => {
+            HttpResponse::ServiceUnavailable().json(readiness)
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::core::error::ErrorCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    ZhCn,
+    En,
+}
+
+impl Locale {
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let tag = tag.trim().to_ascii_lowercase();
+        if tag == "zh" || tag.starts_with("zh-") {
+            Some(Self::ZhCn)
+        } else if tag == "en" || tag.starts_with("en-") {
+            Some(Self::En)
+        } else {
+            None
+        }
+    }
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Self::ZhCn => "zh-CN",
+            Self::En => "en",
+        }
+    }
+
+    /// Picks the supported locale with the highest quality from an
+    /// `Accept-Language` header, e.g. `zh-CN,zh;q=0.9,en;q=0.8`.
+    pub fn negotiate(accept_language: Option<&str>, default: Self) -> Self {
+        let Some(accept_language) = accept_language else {
+            return default;
+        };
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                (quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges
+            .into_iter()
+            .find_map(|(tag, _)| match tag {
+                "*" => Some(default),
+                tag => Self::from_tag(tag),
+            })
+            .unwrap_or(default)
+    }
+}
+
+/// The message shown to clients for `code`.
+pub fn message(code: ErrorCode, locale: Locale) -> &'static str {
+    use ErrorCode::*;
+    match locale {
+        Locale::ZhCn => match code {
+            BadRequest => "请求无效",
+            InvalidQuery => "查询参数无效",
+            InvalidBody => "请求体无效",
+            MissingContentType => "缺少 Content-Type",
+            Unauthorized => "未登录或登录已失效",
+            AuthTokenMissing => "缺少登录凭证",
+            AuthTokenInvalid => "登录凭证无效或已过期",
+            Forbidden => "没有权限",
+            DogNotOwned => "只能修改自己的狗狗",
+            NotFound => "资源不存在",
+            RouteNotFound => "接口不存在",
+            UserNotFound => "用户不存在",
+            MethodNotAllowed => "不支持的请求方法",
+            Conflict => "资源冲突",
+            PayloadTooLarge => "上传内容过大",
+            UrlTooLong => "请求地址过长",
+            HeadersTooLarge => "请求头过大",
+            TooManyRequests => "请求过于频繁，请稍后再试",
+            SmsCodeInvalid => "短信验证码错误",
+            SmsCodeUsed => "短信验证码已被使用",
+            SmsLoginAttemptsExceeded => "验证码尝试次数过多，请稍后再试",
+            PhoneInvalid => "手机号格式不正确",
+            SmsSendCooldown => "验证码发送过于频繁，请稍后再试",
+            SmsSendLimitExceeded => "今日验证码发送次数已达上限",
+            LoginLocked => "登录失败次数过多，请稍后再试",
+            IpDenied => "当前网络不允许访问",
+            InternalError | UpstreamError => "服务器内部错误",
+            UpstreamUnavailable => "服务暂时不可用",
+            UpstreamTimeout => "服务响应超时",
+        },
+        Locale::En => match code {
+            BadRequest => "bad request",
+            InvalidQuery => "invalid query parameters",
+            InvalidBody => "invalid request body",
+            MissingContentType => "no content type",
+            Unauthorized => "unauthorized",
+            AuthTokenMissing => "auth token not exists",
+            AuthTokenInvalid => "invalid auth token",
+            Forbidden => "permission denied",
+            DogNotOwned => "no permission",
+            NotFound => "resource not found",
+            RouteNotFound => "route not found",
+            UserNotFound => "user not exists",
+            MethodNotAllowed => "method not allowed",
+            Conflict => "resource conflict",
+            PayloadTooLarge => "payload too large",
+            UrlTooLong => "url too long",
+            HeadersTooLarge => "request headers too large",
+            TooManyRequests => "too many requests",
+            SmsCodeInvalid => "invalid sms verification code",
+            SmsCodeUsed => "sms verification code has been used",
+            SmsLoginAttemptsExceeded => {
+                "too many sms verification code login attempts"
+            }
+            PhoneInvalid => "invalid phone number",
+            SmsSendCooldown => "sms verification code sent recently",
+            SmsSendLimitExceeded => "too many sms verification codes today",
+            LoginLocked => "too many failed logins, try again later",
+            IpDenied => "access denied from this network",
+            InternalError | UpstreamError => "internal error",
+            UpstreamUnavailable => "service temporarily unavailable",
+            UpstreamTimeout => "service timed out",
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_by_quality() {
+        for (header, expected) in [
+            (None, Locale::ZhCn),
+            (Some("en-US,en;q=0.9"), Locale::En),
+            (Some("fr;q=1.0, en;q=0.5, zh-CN;q=0.8"), Locale::ZhCn),
+            (Some("zh-Hans-CN"), Locale::ZhCn),
+            (Some("fr, de"), Locale::ZhCn),
+            (Some("zh;q=0, en"), Locale::En),
+            (Some("*"), Locale::ZhCn),
+        ] {
+            assert_eq!(
+                Locale::negotiate(header, Locale::ZhCn),
+                expected,
+                "{:?}",
+                header
+            );
+        }
+    }
+}
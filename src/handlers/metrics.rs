@@ -0,0 +1,9 @@
+use actix_web::{web::Data, HttpResponse};
+
+use crate::utils::metrics::Metrics;
+
+pub async fn metrics(metrics: Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
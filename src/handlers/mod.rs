@@ -1,2 +1,10 @@
+pub mod accounts;
+pub mod admin;
 pub mod common;
+pub mod dogs;
 pub mod error;
+pub mod health;
+pub mod messages;
+pub mod metrics;
+pub mod uploads;
+pub mod walk_requests;
@@ -0,0 +1,92 @@
+use actix_web::{
+    body::SizedStream,
+    http::header::{CONTENT_LENGTH, CONTENT_TYPE},
+    web::{Data, Path, Payload},
+    HttpRequest, HttpResponse,
+};
+use serde_json::json;
+
+use crate::{
+    core::{
+        clients::{
+            auth::AuthClient, dog::DogClient,
+            sms_verification_code::SMSVerificationCodeClient,
+            upload::UploadClient, walk_request::WalkRequestClient,
+        },
+        error::{Error, ErrorCode, ErrorKind},
+        service::Service,
+    },
+    utils::{io::payload_to_stream, restful::UserID},
+};
+
+/// Maximum number of bytes accepted by a single upload.
+pub struct UploadLimit(pub usize);
+
+pub(crate) async fn upload<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    limit: Data<UploadLimit>,
+    user_id: UserID,
+    req: HttpRequest,
+    payload: Payload,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .ok_or(
+            Error::new(ErrorKind::Validation, "no content type")
+                .with_code(ErrorCode::MissingContentType),
+        )?
+        .to_str()
+        .map_err(Error::wrap(ErrorKind::Validation))?
+        .to_owned();
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if matches!(content_length, Some(len) if len > limit.0) {
+        return Err(Error::new(
+            ErrorKind::PayloadTooLarge,
+            format!("payload exceeds the limit of {} bytes", limit.0),
+        )
+        .with_details(json!({ "limit": limit.0 })));
+    }
+    let stream = service
+        .upload(
+            &content_type,
+            &user_id.0,
+            limit.0,
+            payload_to_stream(payload),
+        )
+        .await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
+
+pub(crate) async fn download<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    id: Path<String>,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let download = service.download(&id).await?;
+    let mut builder = HttpResponse::Ok();
+    if let Some(content_type) = download.content_type {
+        builder.content_type(content_type);
+    }
+    Ok(match download.content_length {
+        Some(len) => builder.body(SizedStream::new(len, download.body)),
+        None => builder.streaming(download.body),
+    })
+}
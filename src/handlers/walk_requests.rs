@@ -0,0 +1,135 @@
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use serde::Deserialize;
+
+use crate::{
+    core::{
+        clients::{
+            auth::AuthClient,
+            dog::DogClient,
+            sms_verification_code::SMSVerificationCodeClient,
+            upload::UploadClient,
+            walk_request::{WalkRequestClient, WalkRequestCreate},
+        },
+        common::Pagination,
+        error::Error,
+        service::Service,
+    },
+    utils::restful::{Query, UserID},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct NearbyParams {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius: Option<f64>,
+    pub page: i32,
+    pub size: i32,
+}
+
+pub(crate) async fn nearby_walk_requests<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    Query(params): Query<NearbyParams>,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let requests = service
+        .nearby_requests(
+            params.longitude,
+            params.latitude,
+            params.radius,
+            Pagination {
+                page: params.page,
+                size: params.size,
+            },
+        )
+        .await?;
+    Ok(HttpResponse::Ok().json(requests))
+}
+
+pub(crate) async fn create_walk_request<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    user_id: UserID,
+    Json(create): Json<WalkRequestCreate>,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service.create_walk_request(&user_id.0, &create).await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
+
+pub(crate) async fn accept_walk_request<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    user_id: UserID,
+    id: Path<String>,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service.accept_walk_request(&user_id.0, &id).await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
+
+pub(crate) async fn cancel_walk_request<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    user_id: UserID,
+    id: Path<String>,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service.cancel_walk_request(&user_id.0, &id).await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
+
+pub(crate) async fn start_walk_request<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    user_id: UserID,
+    id: Path<String>,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service.start_walk_request(&user_id.0, &id).await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
+
+pub(crate) async fn finish_walk_request<A, S, D, W, U>(
+    service: Data<Service<A, S, D, W, U>>,
+    user_id: UserID,
+    id: Path<String>,
+) -> Result<HttpResponse, Error>
+where
+    A: AuthClient,
+    S: SMSVerificationCodeClient,
+    D: DogClient,
+    W: WalkRequestClient,
+    U: UploadClient,
+{
+    let stream = service.finish_walk_request(&user_id.0, &id).await?;
+    Ok(HttpResponse::Ok().streaming(stream))
+}
@@ -0,0 +1,14 @@
+mod app;
+mod clients;
+mod config;
+pub mod core;
+#[cfg(any(test, feature = "fakes"))]
+pub mod fakes;
+mod handlers;
+mod middlewares;
+mod state;
+mod utils;
+
+pub use app::Gateway;
+pub use config::Config;
+pub use utils::access_log::ACCESS_LOG_TARGET;
@@ -0,0 +1,230 @@
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    web::Bytes,
+    Error, HttpMessage,
+};
+use futures::future::LocalBoxFuture;
+use futures::{Stream, TryStreamExt};
+use serde::Serialize;
+use std::cell::Cell;
+use std::future::{ready, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use crate::middlewares::metrics::route_of;
+use crate::utils::access_log::{self, ACCESS_LOG_TARGET};
+use crate::utils::network::client_ip;
+use crate::utils::redact::Redactor;
+use crate::utils::request_id::REQUEST_ID_HEADER;
+
+/// Writes one JSON line per request to the access log target, the line is
+/// written once the response body was sent so that its size is known.
+#[derive(Clone)]
+pub struct JsonAccessLogMiddlewareFactory {
+    scopes: &'static [&'static str],
+    redactor: Arc<Redactor>,
+}
+
+impl JsonAccessLogMiddlewareFactory {
+    /// `scopes` label requests matching no route, see `route_of`, paths are
+    /// masked by `redactor`.
+    pub fn new(
+        scopes: &'static [&'static str],
+        redactor: Arc<Redactor>,
+    ) -> Self {
+        Self { scopes, redactor }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AccessLogEntry {
+    timestamp: String,
+    request_id: Option<String>,
+    user_id: Option<String>,
+    method: String,
+    path: String,
+    route: String,
+    status: u16,
+    upstream: Option<&'static str>,
+    upstream_latency_ms: Option<f64>,
+    latency_ms: f64,
+    bytes_in: u64,
+    bytes_out: Option<u64>,
+    client_ip: Option<String>,
+}
+
+impl AccessLogEntry {
+    fn write(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => log::info!(target: ACCESS_LOG_TARGET, "{}", line),
+            Err(e) => log::error!("failed to write access log: {}", e),
+        }
+    }
+}
+
+/// Counts the bytes sent and writes the entry when dropped, i.e. once the
+/// response is done.
+pub struct LoggedBody {
+    body: BoxBody,
+    entry: Option<AccessLogEntry>,
+    bytes_out: u64,
+}
+
+impl MessageBody for LoggedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let poll = Pin::new(&mut self.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.bytes_out += chunk.len() as u64;
+        }
+        poll
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.bytes_out = Some(self.bytes_out);
+            entry.write();
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for JsonAccessLogMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<LoggedBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = JsonAccessLogMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(JsonAccessLogMiddlewareService {
+            scopes: self.scopes,
+            redactor: self.redactor.clone(),
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct JsonAccessLogMiddlewareService<S> {
+    scopes: &'static [&'static str],
+    redactor: Arc<Redactor>,
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for JsonAccessLogMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<LoggedBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let mut entry = AccessLogEntry {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            request_id: header(&req, REQUEST_ID_HEADER),
+            user_id: None,
+            method: req.method().to_string(),
+            path: self.redactor.redact(req.path()).into_owned(),
+            route: String::new(),
+            status: 0,
+            upstream: None,
+            upstream_latency_ms: None,
+            latency_ms: 0.0,
+            bytes_in: 0,
+            bytes_out: None,
+            client_ip: client_ip(req.request()).map(|ip| ip.to_string()),
+        };
+        let bytes_in = Rc::new(Cell::new(0));
+        let counted = req.take_payload().inspect_ok({
+            let bytes_in = bytes_in.clone();
+            move |chunk| bytes_in.set(bytes_in.get() + chunk.len() as u64)
+        });
+        let counted: Pin<Box<dyn Stream<Item = _>>> = Box::pin(counted);
+        req.set_payload(Payload::from(counted));
+        let upstream_call = Rc::new(Cell::new(None));
+        let service = self.service.clone();
+        let fut =
+            access_log::scope(upstream_call.clone(), move || service.call(req));
+        let scopes = self.scopes;
+        Box::pin(async move {
+            let result = fut.await;
+            entry.latency_ms = millis(started.elapsed());
+            entry.bytes_in = bytes_in.get();
+            if let Some(call) = upstream_call.get() {
+                entry.upstream = Some(call.name);
+                entry.upstream_latency_ms = Some(millis(call.latency));
+            }
+            match result {
+                Ok(res) => {
+                    // Set by the auth middleware once the token is verified.
+                    entry.user_id = res
+                        .request()
+                        .headers()
+                        .get("X-User-ID")
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_owned);
+                    entry.route = route_of(
+                        res.request().match_pattern(),
+                        &entry.path,
+                        scopes,
+                    );
+                    entry.status = res.status().as_u16();
+                    Ok(res.map_into_boxed_body().map_body(|_, body| {
+                        LoggedBody {
+                            body,
+                            entry: Some(entry),
+                            bytes_out: 0,
+                        }
+                    }))
+                }
+                Err(e) => {
+                    // The body of error responses is rendered further out.
+                    entry.route = route_of(None, &entry.path, scopes);
+                    entry.status = e.as_response_error().status_code().as_u16();
+                    entry.write();
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+fn header(req: &ServiceRequest, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned)
+}
+
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
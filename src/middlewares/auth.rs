@@ -1,48 +1,106 @@
-use actix_web::error::{ErrorInternalServerError, ErrorUnauthorized};
+use actix_web::error::ErrorInternalServerError;
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    Error, HttpMessage,
 };
 use reqwest::header::{HeaderName, HeaderValue};
 use std::future::Future;
 use std::pin::Pin;
+use std::time::Duration;
 use std::{
     future::{ready, Ready},
     task::Poll,
 };
 
 use crate::core::clients::auth::AuthClient;
+use crate::core::error::{Error as GatewayError, ErrorCode, ErrorKind};
+use crate::state::{StateBackend, StateStore};
+use crate::utils::metrics::Metrics;
+use crate::utils::telemetry;
 use std::str::FromStr;
 use std::sync::Arc;
 
+/// The user verified by auth, kept in the request extensions.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser(pub String);
+
 type ServiceFuture =
     Pin<Box<dyn Future<Output = Result<ServiceResponse, Error>>>>;
 
+/// Remembers the users of verified tokens for `ttl`, so that auth is not
+/// asked on every request. A zero `ttl` disables it. Tokens revoked at auth
+/// keep working until they expire here. Failures of the state backend are
+/// logged and treated as misses.
+#[derive(Clone)]
+pub struct TokenCache {
+    state: StateBackend,
+    ttl: Duration,
+}
+
+impl TokenCache {
+    pub fn new(state: StateBackend, ttl: Duration) -> Self {
+        Self { state, ttl }
+    }
+
+    fn key(token: &str) -> String {
+        format!("auth_tokens:{}", token)
+    }
+
+    async fn get(&self, token: &str) -> Option<String> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        self.state.get(&Self::key(token)).await.unwrap_or_else(|e| {
+            log::warn!("failed to read the token cache: {}", e);
+            None
+        })
+    }
+
+    async fn insert(&self, token: &str, user_id: &str) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        if let Err(e) = self
+            .state
+            .set(&Self::key(token), user_id, Some(self.ttl))
+            .await
+        {
+            log::warn!("failed to write the token cache: {}", e);
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AuthMiddlewareFactory<C>
 where
     C: AuthClient + Clone,
 {
     auth_client: C,
+    token_cache: TokenCache,
+    metrics: Metrics,
 }
 
 impl<C> AuthMiddlewareFactory<C>
 where
     C: AuthClient + Clone,
 {
-    pub fn new(auth_client: C) -> Self {
-        Self { auth_client }
+    pub fn new(
+        auth_client: C,
+        token_cache: TokenCache,
+        metrics: Metrics,
+    ) -> Self {
+        Self {
+            auth_client,
+            token_cache,
+            metrics,
+        }
     }
 }
 
 impl<C, S> Transform<S, ServiceRequest> for AuthMiddlewareFactory<C>
 where
-    S: Service<
-            ServiceRequest,
-            Response = ServiceResponse,
-            Error = Error,
-            Future = ServiceFuture,
-        > + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error>
+        + 'static,
     C: AuthClient + Clone + 'static,
 {
     type Response = ServiceResponse;
@@ -54,6 +112,8 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddlewareService {
             auth_client: self.auth_client.clone(),
+            token_cache: self.token_cache.clone(),
+            metrics: self.metrics.clone(),
             service: Arc::new(service),
         }))
     }
@@ -64,17 +124,33 @@ where
     C: AuthClient + Clone,
 {
     auth_client: C,
+    token_cache: TokenCache,
+    metrics: Metrics,
     service: Arc<S>,
 }
 
+/// Passes `req` on as a request of the verified `user_id`.
+async fn authenticated<S>(
+    service: Arc<S>,
+    mut req: ServiceRequest,
+    user_id: String,
+) -> Result<ServiceResponse, Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error>,
+{
+    let uid_header_key =
+        HeaderName::from_str("X-User-ID").map_err(ErrorInternalServerError)?;
+    let uid_header_value =
+        HeaderValue::from_str(&user_id).map_err(ErrorInternalServerError)?;
+    req.headers_mut().insert(uid_header_key, uid_header_value);
+    req.extensions_mut().insert(AuthenticatedUser(user_id));
+    service.call(req).await
+}
+
 impl<C, S> Service<ServiceRequest> for AuthMiddlewareService<C, S>
 where
-    S: Service<
-            ServiceRequest,
-            Response = ServiceResponse,
-            Error = Error,
-            Future = ServiceFuture,
-        > + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error>
+        + 'static,
     C: AuthClient + Clone + 'static,
 {
     type Error = Error;
@@ -88,30 +164,154 @@ where
         Poll::Ready(Ok(()))
     }
 
-    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+    fn call(&self, req: ServiceRequest) -> Self::Future {
         if let Some(hv) = req.headers().get("X-Auth-Token") {
             if let Ok(token) = hv.to_str() {
                 let token = token.to_owned();
                 let next_service = self.service.clone();
                 let auth_client = self.auth_client.clone();
+                let token_cache = self.token_cache.clone();
+                let metrics = self.metrics.clone();
                 return Box::pin(async move {
-                    match auth_client.verify_token(&token).await {
+                    if let Some(id) = token_cache.get(&token).await {
+                        metrics.auth_verified("cached");
+                        return authenticated(next_service, req, id).await;
+                    }
+                    let verified = telemetry::in_span(
+                        "auth.verify_token",
+                        auth_client.verify_token(&token),
+                    );
+                    match verified.await {
                         Ok(id) => {
-                            let uid_header_key =
-                                HeaderName::from_str("X-User-ID")
-                                    .map_err(ErrorInternalServerError)?;
-                            let uid_header_value =
-                                HeaderValue::from_str(&id)
-                                    .map_err(ErrorInternalServerError)?;
-                            req.headers_mut()
-                                .insert(uid_header_key, uid_header_value);
-                            next_service.call(req).await
+                            metrics.auth_verified("valid");
+                            token_cache.insert(&token, &id).await;
+                            authenticated(next_service, req, id).await
+                        }
+                        Err(e) if e.kind.status_code().is_server_error() => {
+                            metrics.auth_verified("error");
+                            Err(e.into())
+                        }
+                        Err(e) => {
+                            metrics.auth_verified("invalid");
+                            Err(GatewayError::new(
+                                ErrorKind::Authentication,
+                                &e.cause,
+                            )
+                            .with_code(ErrorCode::AuthTokenInvalid)
+                            .with_source(e)
+                            .into())
                         }
-                        Err(e) => Err(ErrorUnauthorized(e)),
                     }
                 });
             }
         }
-        Box::pin(ready(Err(ErrorUnauthorized("auth token not exists"))))
+        self.metrics.auth_verified("missing");
+        Box::pin(ready(Err(GatewayError::new(
+            ErrorKind::Authentication,
+            "auth token not exists",
+        )
+        .with_code(ErrorCode::AuthTokenMissing)
+        .into())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::auth::FakeAuthClient;
+    use actix_web::{
+        http::StatusCode,
+        test::{self, TestRequest},
+        web, App, HttpRequest, HttpResponse,
+    };
+
+    async fn echo_user_id(req: HttpRequest) -> HttpResponse {
+        let user_id = req.headers().get("X-User-ID").unwrap().clone();
+        HttpResponse::Ok().body(user_id.to_str().unwrap().to_owned())
+    }
+
+    #[actix_web::test]
+    async fn verified_token_sets_user_id() {
+        let auth_client = FakeAuthClient::new().with_token("t1", "u1");
+        let app = test::init_service(
+            App::new().service(
+                web::scope("apis")
+                    .wrap(AuthMiddlewareFactory::new(
+                        auth_client.clone(),
+                        TokenCache::new(StateBackend::memory(), Duration::ZERO),
+                        Metrics::new(),
+                    ))
+                    .route("/me", web::get().to(echo_user_id)),
+            ),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/apis/me")
+            .insert_header(("X-Auth-Token", "t1"))
+            .insert_header(("X-User-ID", "spoofed"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "u1");
+        assert_eq!(auth_client.script.calls_to("verify_token").len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn missing_or_invalid_token_is_unauthorized() {
+        let app = test::init_service(
+            App::new().service(
+                web::scope("apis")
+                    .wrap(AuthMiddlewareFactory::new(
+                        FakeAuthClient::new().with_token("t1", "u1"),
+                        TokenCache::new(StateBackend::memory(), Duration::ZERO),
+                        Metrics::new(),
+                    ))
+                    .route("/me", web::get().to(echo_user_id)),
+            ),
+        )
+        .await;
+        for req in [
+            TestRequest::get().uri("/apis/me").to_request(),
+            TestRequest::get()
+                .uri("/apis/me")
+                .insert_header(("X-Auth-Token", "t2"))
+                .to_request(),
+        ] {
+            let err = test::try_call_service(&app, req).await.err().unwrap();
+            assert_eq!(
+                err.as_response_error().status_code(),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn verified_tokens_are_cached_until_the_ttl() {
+        let auth_client = FakeAuthClient::new().with_token("t1", "u1");
+        let ttl = Duration::from_millis(50);
+        let app = test::init_service(
+            App::new().service(
+                web::scope("apis")
+                    .wrap(AuthMiddlewareFactory::new(
+                        auth_client.clone(),
+                        TokenCache::new(StateBackend::memory(), ttl),
+                        Metrics::new(),
+                    ))
+                    .route("/me", web::get().to(echo_user_id)),
+            ),
+        )
+        .await;
+        let me = || {
+            TestRequest::get()
+                .uri("/apis/me")
+                .insert_header(("X-Auth-Token", "t1"))
+                .to_request()
+        };
+        for _ in 0..2 {
+            assert_eq!(test::call_and_read_body(&app, me()).await, "u1");
+        }
+        assert_eq!(auth_client.script.calls_to("verify_token").len(), 1);
+        std::thread::sleep(ttl);
+        assert_eq!(test::call_and_read_body(&app, me()).await, "u1");
+        assert_eq!(auth_client.script.calls_to("verify_token").len(), 2);
     }
 }
@@ -0,0 +1,387 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::{
+        header::{
+            self, HeaderMap, HeaderName, HeaderValue,
+            ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+            ORIGIN,
+        },
+        Method,
+    },
+    Error, HttpResponse,
+};
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
+
+use crate::core::error::{Error as GatewayError, ErrorKind};
+use crate::middlewares::metrics::{route_matches, route_of};
+
+/// How browsers may call some routes from other origins.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsPolicy {
+    /// Route patterns such as `/apis/dogs/{dog_id}`, `/apis/*` matches every
+    /// route below `/apis` and `*` every route.
+    pub routes: Vec<String>,
+    /// Origins such as `https://admin.example.com`, `https://*.example.com`
+    /// for its subdomains or `*` for any.
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    /// `*` allows whatever headers are requested.
+    #[serde(default = "default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+    #[serde(default = "default_exposed_headers")]
+    pub exposed_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    pub max_age_secs: Option<u64>,
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_allowed_headers() -> Vec<String> {
+    [
+        "Accept-Language",
+        "Content-Type",
+        "X-Auth-Token",
+        "X-Request-ID",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_exposed_headers() -> Vec<String> {
+    [
+        "X-Request-ID",
+        "Retry-After",
+        "RateLimit-Limit",
+        "RateLimit-Remaining",
+        "RateLimit-Reset",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Whether `origin` is matched by `pattern`, where `*.` stands for one or
+/// more subdomain labels.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" || pattern.eq_ignore_ascii_case(origin) {
+        return true;
+    }
+    let Some((scheme, domain)) = pattern.split_once("*.") else {
+        return false;
+    };
+    let origin = origin.to_ascii_lowercase();
+    let suffix = format!(".{}", domain.to_ascii_lowercase());
+    origin
+        .strip_prefix(&scheme.to_ascii_lowercase())
+        .and_then(|rest| rest.strip_suffix(&suffix))
+        .is_some_and(|subdomain| {
+            !subdomain.is_empty()
+                && subdomain
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        })
+}
+
+impl CorsPolicy {
+    /// Parses a JSON array of policies, the first one matching a route
+    /// applies to it.
+    pub fn parse_all(policies: &str) -> Result<Vec<Self>, String> {
+        let policies: Vec<Self> =
+            serde_json::from_str(policies).map_err(|e| e.to_string())?;
+        for policy in &policies {
+            if policy.allow_credentials
+                && policy.allowed_origins.iter().any(|o| o == "*")
+            {
+                return Err("credentials cannot be allowed for any origin \
+                            (*), list the origins instead"
+                    .to_owned());
+            }
+            for header in policy
+                .allowed_headers
+                .iter()
+                .chain(&policy.exposed_headers)
+                .filter(|h| *h != "*")
+            {
+                HeaderName::try_from(header.as_str())
+                    .map_err(|_| format!("invalid header {}", header))?;
+            }
+            for method in &policy.allowed_methods {
+                Method::try_from(method.as_str())
+                    .map_err(|_| format!("invalid method {}", method))?;
+            }
+        }
+        Ok(policies)
+    }
+
+    fn applies_to(&self, route: &str) -> bool {
+        self.routes
+            .iter()
+            .any(|pattern| route_matches(pattern, route))
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|pattern| origin_matches(pattern, origin))
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|m| m == "*" || m.eq_ignore_ascii_case(method))
+    }
+
+    fn allows_headers(&self, requested: &str) -> bool {
+        requested
+            .split(',')
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .all(|requested| {
+                self.allowed_headers
+                    .iter()
+                    .any(|h| h == "*" || h.eq_ignore_ascii_case(requested))
+            })
+    }
+
+    /// Headers of every response to an allowed origin.
+    fn insert_origin_headers(&self, headers: &mut HeaderMap, origin: &str) {
+        let any = self.allowed_origins.iter().any(|o| o == "*");
+        let allowed = if any && !self.allow_credentials {
+            HeaderValue::from_static("*")
+        } else {
+            match HeaderValue::from_str(origin) {
+                Ok(origin) => origin,
+                Err(_) => return,
+            }
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+
+    /// Headers of responses to actual requests from an allowed origin.
+    fn insert_response_headers(&self, headers: &mut HeaderMap, origin: &str) {
+        self.insert_origin_headers(headers, origin);
+        if let Some(exposed) = joined(&self.exposed_headers) {
+            headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+        }
+    }
+}
+
+fn joined(values: &[String]) -> Option<HeaderValue> {
+    if values.is_empty() {
+        return None;
+    }
+    HeaderValue::from_str(&values.join(", ")).ok()
+}
+
+/// Answers CORS preflights without calling upstreams and adds CORS headers
+/// to responses for allowed origins, error responses included. Wrap it
+/// outside of the error envelope.
+#[derive(Clone)]
+pub struct CorsMiddlewareFactory {
+    policies: Arc<Vec<CorsPolicy>>,
+    scopes: &'static [&'static str],
+}
+
+impl CorsMiddlewareFactory {
+    pub fn new(
+        policies: Vec<CorsPolicy>,
+        scopes: &'static [&'static str],
+    ) -> Self {
+        Self {
+            policies: Arc::new(policies),
+            scopes,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CorsMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CorsMiddlewareService {
+            policies: self.policies.clone(),
+            scopes: self.scopes,
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct CorsMiddlewareService<S> {
+    policies: Arc<Vec<CorsPolicy>>,
+    scopes: &'static [&'static str],
+    service: Rc<S>,
+}
+
+impl<S> CorsMiddlewareService<S> {
+    fn policy(&self, req: &ServiceRequest) -> Option<&CorsPolicy> {
+        let route = route_of(req.match_pattern(), req.path(), self.scopes);
+        self.policies
+            .iter()
+            .find(|policy| policy.applies_to(&route))
+    }
+}
+
+fn preflight(
+    policy: Option<&CorsPolicy>,
+    origin: &str,
+    req: &ServiceRequest,
+) -> Result<HttpResponse, GatewayError> {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|v: &HeaderValue| v.to_str().ok())
+    };
+    let method = header(ACCESS_CONTROL_REQUEST_METHOD).unwrap_or_default();
+    let headers = header(ACCESS_CONTROL_REQUEST_HEADERS).unwrap_or_default();
+    let rejected = |cause| GatewayError::new(ErrorKind::Authorization, cause);
+    let policy = policy.ok_or_else(|| rejected("cors is not enabled"))?;
+    if !policy.allows_origin(origin) {
+        return Err(rejected("origin not allowed"));
+    }
+    if !policy.allows_method(method) {
+        return Err(rejected("method not allowed by cors"));
+    }
+    if !policy.allows_headers(headers) {
+        return Err(rejected("headers not allowed by cors"));
+    }
+    let mut res = HttpResponse::NoContent().finish();
+    let res_headers = res.headers_mut();
+    policy.insert_origin_headers(res_headers, origin);
+    for name in [
+        ACCESS_CONTROL_REQUEST_METHOD,
+        ACCESS_CONTROL_REQUEST_HEADERS,
+    ] {
+        res_headers.append(header::VARY, HeaderValue::from(name));
+    }
+    // Echoed, since wildcards are not honoured along with credentials.
+    if let Ok(method) = HeaderValue::from_str(method) {
+        res_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, method);
+    }
+    if !headers.is_empty() {
+        if let Ok(headers) = HeaderValue::from_str(headers) {
+            res_headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, headers);
+        }
+    }
+    if let Some(max_age) = policy.max_age_secs {
+        res_headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.into());
+    }
+    Ok(res)
+}
+
+impl<S, B> Service<ServiceRequest> for CorsMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let Some(origin) = req
+            .headers()
+            .get(ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+        else {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                fut.await.map(ServiceResponse::map_into_left_body)
+            });
+        };
+        let policy = self.policy(&req).cloned();
+        if req.method() == Method::OPTIONS
+            && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            let res = match preflight(policy.as_ref(), &origin, &req) {
+                Ok(res) => req.into_response(res),
+                Err(e) => req.error_response(e),
+            };
+            return Box::pin(ready(Ok(res.map_into_right_body())));
+        }
+        let policy = policy.filter(|policy| policy.allows_origin(&origin));
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let Some(policy) = policy else {
+                return fut.await.map(ServiceResponse::map_into_left_body);
+            };
+            match fut.await {
+                Ok(mut res) => {
+                    policy.insert_response_headers(res.headers_mut(), &origin);
+                    Ok(res.map_into_left_body())
+                }
+                // Rendered here for the headers to reach the browser too.
+                Err(e) => {
+                    let mut res = e.error_response();
+                    policy.insert_response_headers(res.headers_mut(), &origin);
+                    Err(InternalError::from_response(e, res).into())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins_match_exactly_or_by_subdomain() {
+        for (pattern, origin, expected) in [
+            ("https://a.example.com", "https://a.example.com", true),
+            ("https://a.example.com", "https://b.example.com", false),
+            ("https://*.example.com", "https://a.example.com", true),
+            ("https://*.example.com", "https://a.b.example.com", true),
+            ("https://*.example.com", "https://example.com", false),
+            ("https://*.example.com", "http://a.example.com", false),
+            ("https://*.example.com", "https://a.example.com.evil", false),
+            ("https://*.example.com", "https://evil/.example.com", false),
+            ("*", "https://anything.test", true),
+        ] {
+            assert_eq!(
+                origin_matches(pattern, origin),
+                expected,
+                "{} {}",
+                pattern,
+                origin
+            );
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::header::ACCEPT_LANGUAGE,
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
+
+use crate::handlers::{
+    error::{render_actix_error, RenderOptions},
+    messages::Locale,
+};
+use crate::utils::redact::Redactor;
+
+/// Renders every error response, whether it comes from a handler or from a
+/// middleware such as auth, as the JSON error envelope carrying the request
+/// id.
+#[derive(Clone)]
+pub struct ErrorEnvelopeMiddlewareFactory {
+    sanitize_upstream_errors: bool,
+    default_locale: Locale,
+    redactor: Arc<Redactor>,
+}
+
+impl ErrorEnvelopeMiddlewareFactory {
+    pub fn new(
+        sanitize_upstream_errors: bool,
+        default_locale: Locale,
+        redactor: Arc<Redactor>,
+    ) -> Self {
+        Self {
+            sanitize_upstream_errors,
+            default_locale,
+            redactor,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ErrorEnvelopeMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ErrorEnvelopeMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ErrorEnvelopeMiddlewareService {
+            service: Rc::new(service),
+            sanitize_upstream_errors: self.sanitize_upstream_errors,
+            default_locale: self.default_locale,
+            redactor: self.redactor.clone(),
+        }))
+    }
+}
+
+pub struct ErrorEnvelopeMiddlewareService<S> {
+    service: Rc<S>,
+    sanitize_upstream_errors: bool,
+    default_locale: Locale,
+    redactor: Arc<Redactor>,
+}
+
+impl<S, B> Service<ServiceRequest> for ErrorEnvelopeMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get("X-Request-ID")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+        let locale = Locale::negotiate(
+            req.headers()
+                .get(ACCEPT_LANGUAGE)
+                .and_then(|v| v.to_str().ok()),
+            self.default_locale,
+        );
+        let sanitize = self.sanitize_upstream_errors;
+        let redactor = self.redactor.clone();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let options = RenderOptions {
+                request_id: request_id.as_deref(),
+                locale,
+                sanitize,
+                redactor: &redactor,
+            };
+            match fut.await {
+                Ok(res) => {
+                    let rendered = res
+                        .response()
+                        .error()
+                        .map(|e| render_actix_error(e, &options));
+                    Ok(match rendered {
+                        Some(mut resp) => {
+                            // Keep headers such as Retry-After set alongside
+                            // the error.
+                            for (name, value) in res.headers() {
+                                if !resp.headers().contains_key(name) {
+                                    resp.headers_mut()
+                                        .append(name.clone(), value.clone());
+                                }
+                            }
+                            res.into_response(resp).map_into_right_body()
+                        }
+                        None => res.map_into_left_body(),
+                    })
+                }
+                Err(e) => {
+                    let resp = render_actix_error(&e, &options);
+                    Err(InternalError::from_response(e, resp).into())
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,392 @@
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::ErrorInternalServerError,
+    http::{header::CONTENT_TYPE, StatusCode},
+    Error, HttpMessage, HttpResponse,
+};
+use bytes::{Bytes, BytesMut};
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::task::Poll;
+use std::time::Duration;
+
+use crate::core::error::{Error as GatewayError, ErrorKind};
+use crate::middlewares::auth::AuthenticatedUser;
+use crate::state::{StateBackend, StateStore};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Set on responses replayed from a record.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// How long a request holds its key, longer than any request should take
+/// so that keys of replicas stopped midway become usable again.
+const IN_FLIGHT_TTL: Duration = Duration::from_secs(60);
+
+/// Larger responses are passed on without being recorded.
+const MAX_RECORDED_BODY: usize = 64 * 1024;
+
+/// A successful response, replayed for retries of its request.
+#[derive(Serialize, Deserialize)]
+struct IdempotencyRecord {
+    status: u16,
+    content_type: Option<String>,
+    body: String,
+}
+
+/// Records the successful responses to unsafe requests carrying an
+/// `Idempotency-Key` header for `ttl`, and replays them for retries with the
+/// same key, method and path from the same user. A retry while the first
+/// request is in flight is a conflict. Failures are not recorded, so they
+/// can be retried with the same key.
+///
+/// Wrap it inside auth, keys are per user and requests of unknown users are
+/// passed on as they are. Requests are passed on too if the state backend
+/// fails.
+#[derive(Clone)]
+pub struct IdempotencyMiddlewareFactory {
+    state: StateBackend,
+    ttl: Duration,
+}
+
+impl IdempotencyMiddlewareFactory {
+    pub fn new(state: StateBackend, ttl: Duration) -> Self {
+        Self { state, ttl }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IdempotencyMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IdempotencyMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddlewareService {
+            state: self.state.clone(),
+            ttl: self.ttl,
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct IdempotencyMiddlewareService<S> {
+    state: StateBackend,
+    ttl: Duration,
+    service: Rc<S>,
+}
+
+/// The state key of the request, if it is to be made idempotent.
+fn idempotency_key(req: &ServiceRequest) -> Option<String> {
+    if req.method().is_safe() {
+        return None;
+    }
+    let key = req
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)?
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty())?;
+    let user = req.extensions().get::<AuthenticatedUser>()?.0.clone();
+    Some(format!("{}:{}:{}:{}", user, req.method(), req.path(), key))
+}
+
+fn replay(record: IdempotencyRecord) -> Option<HttpResponse> {
+    let mut res =
+        HttpResponse::build(StatusCode::from_u16(record.status).ok()?);
+    if let Some(content_type) = record.content_type {
+        res.insert_header((CONTENT_TYPE, content_type));
+    }
+    res.insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"));
+    Some(res.body(record.body))
+}
+
+/// Reads `body` up to `MAX_RECORDED_BODY` bytes. Returns the bytes read, or
+/// a body streaming them and the rest if there are more.
+async fn read_body<B>(body: B) -> Result<Result<Bytes, BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+{
+    let mut body = Box::pin(body);
+    let mut read = BytesMut::new();
+    while read.len() <= MAX_RECORDED_BODY {
+        let chunk =
+            futures::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await;
+        match chunk {
+            Some(Ok(chunk)) => read.extend_from_slice(&chunk),
+            Some(Err(e)) => {
+                let e: Box<dyn std::error::Error> = e.into();
+                return Err(ErrorInternalServerError(e));
+            }
+            None => return Ok(Ok(read.freeze())),
+        }
+    }
+    let rest = futures::stream::poll_fn(move |cx| body.as_mut().poll_next(cx));
+    let stream = futures::stream::once(ready(Ok::<_, B::Error>(read.freeze())))
+        .chain(rest);
+    Ok(Err(BoxBody::new(actix_web::body::BodyStream::new(stream))))
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let Some(key) = idempotency_key(&req) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                fut.await.map(ServiceResponse::map_into_boxed_body)
+            });
+        };
+        let record_key = format!("idempotency:{}", key);
+        let lock_key = format!("idempotency_locks:{}", key);
+        let state = self.state.clone();
+        let ttl = self.ttl;
+        let service = self.service.clone();
+        Box::pin(async move {
+            let record = match state.get(&record_key).await {
+                Ok(record) => record,
+                Err(e) => {
+                    log::warn!("failed to read an idempotency record: {}", e);
+                    let res = service.call(req).await?;
+                    return Ok(res.map_into_boxed_body());
+                }
+            };
+            if let Some(res) = record
+                .and_then(|record| serde_json::from_str(&record).ok())
+                .and_then(replay)
+            {
+                return Ok(req.into_response(res));
+            }
+            match state.insert_if_absent(&lock_key, IN_FLIGHT_TTL).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Ok(req.error_response(GatewayError::new(
+                        ErrorKind::Conflict,
+                        "a request with this idempotency key is in progress",
+                    )))
+                }
+                Err(e) => {
+                    log::warn!("failed to lock an idempotency key: {}", e);
+                    let res = service.call(req).await?;
+                    return Ok(res.map_into_boxed_body());
+                }
+            }
+            let res = service.call(req).await;
+            let res = match res {
+                Ok(res) if res.status().is_success() => res,
+                res => {
+                    if let Err(e) = state.remove(&lock_key).await {
+                        log::warn!(
+                            "failed to unlock an idempotency key: {}",
+                            e
+                        );
+                    }
+                    return res.map(ServiceResponse::map_into_boxed_body);
+                }
+            };
+            let status = res.status().as_u16();
+            let content_type = res
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned);
+            let (req, res) = res.into_parts();
+            let (res, body) = res.into_parts();
+            let body = match read_body(body).await? {
+                Ok(body) => {
+                    if let Ok(text) = std::str::from_utf8(&body) {
+                        let record =
+                            serde_json::to_string(&IdempotencyRecord {
+                                status,
+                                content_type,
+                                body: text.to_owned(),
+                            })
+                            .map_err(ErrorInternalServerError)?;
+                        if let Err(e) =
+                            state.set(&record_key, &record, Some(ttl)).await
+                        {
+                            log::warn!(
+                                "failed to write an idempotency record: {}",
+                                e
+                            );
+                        }
+                    }
+                    BoxBody::new(body)
+                }
+                Err(body) => body,
+            };
+            if let Err(e) = state.remove(&lock_key).await {
+                log::warn!("failed to unlock an idempotency key: {}", e);
+            }
+            Ok(ServiceResponse::new(req, res.set_body(body)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        test::{self, TestRequest},
+        web, App,
+    };
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    async fn create(created: web::Data<AtomicU32>) -> HttpResponse {
+        let id = created.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Created().json(serde_json::json!({ "id": id }))
+    }
+
+    #[actix_web::test]
+    async fn retries_with_the_same_key_are_replayed() {
+        let created = web::Data::new(AtomicU32::new(0));
+        let app = test::init_service(
+            App::new()
+                .app_data(created.clone())
+                .wrap(IdempotencyMiddlewareFactory::new(
+                    StateBackend::memory(),
+                    Duration::from_secs(60),
+                ))
+                // Marks requests as those of the user in X-User-ID, as auth
+                // would.
+                .wrap_fn(|req, srv| {
+                    let user = req
+                        .headers()
+                        .get("X-User-ID")
+                        .and_then(|v| v.to_str().ok())
+                        .map(|user| AuthenticatedUser(user.to_owned()));
+                    if let Some(user) = user {
+                        req.extensions_mut().insert(user);
+                    }
+                    actix_web::dev::Service::call(srv, req)
+                })
+                .route("/dogs", web::post().to(create)),
+        )
+        .await;
+        let post = |user: Option<&str>, key: Option<&str>| {
+            let mut req = TestRequest::post().uri("/dogs");
+            if let Some(user) = user {
+                req = req.insert_header(("X-User-ID", user.to_owned()));
+            }
+            if let Some(key) = key {
+                req =
+                    req.insert_header((IDEMPOTENCY_KEY_HEADER, key.to_owned()));
+            }
+            req.to_request()
+        };
+        let first =
+            test::call_service(&app, post(Some("u1"), Some("k1"))).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(!first.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER));
+        let first = test::read_body(first).await;
+        let retry =
+            test::call_service(&app, post(Some("u1"), Some("k1"))).await;
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(
+            retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(),
+            "true"
+        );
+        assert_eq!(
+            retry.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert_eq!(test::read_body(retry).await, first);
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+        for (user, key) in [
+            (Some("u1"), Some("k2")),
+            (Some("u2"), Some("k1")),
+            (Some("u1"), None),
+            (None, Some("k1")),
+        ] {
+            test::call_service(&app, post(user, key)).await;
+        }
+        assert_eq!(created.load(Ordering::SeqCst), 5);
+    }
+
+    #[actix_web::test]
+    async fn keys_in_flight_conflict_and_failures_are_not_recorded() {
+        let state = StateBackend::memory();
+        let failing = Arc::new(AtomicBool::new(true));
+        let app = test::init_service(
+            App::new()
+                .wrap(IdempotencyMiddlewareFactory::new(
+                    state.clone(),
+                    Duration::from_secs(60),
+                ))
+                .wrap_fn(|req, srv| {
+                    req.extensions_mut()
+                        .insert(AuthenticatedUser("u1".to_owned()));
+                    actix_web::dev::Service::call(srv, req)
+                })
+                .route(
+                    "/dogs",
+                    web::post().to(move || {
+                        let failing = failing.clone();
+                        async move {
+                            if failing.swap(false, Ordering::SeqCst) {
+                                HttpResponse::ServiceUnavailable().finish()
+                            } else {
+                                HttpResponse::Created().finish()
+                            }
+                        }
+                    }),
+                ),
+        )
+        .await;
+        let post = || {
+            TestRequest::post()
+                .uri("/dogs")
+                .insert_header((IDEMPOTENCY_KEY_HEADER, "k1"))
+                .to_request()
+        };
+        let res = test::call_service(&app, post()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        state
+            .insert_if_absent(
+                "idempotency_locks:u1:POST:/dogs:k1",
+                IN_FLIGHT_TTL,
+            )
+            .await
+            .unwrap();
+        let res = test::call_service(&app, post()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        state
+            .remove("idempotency_locks:u1:POST:/dogs:k1")
+            .await
+            .unwrap();
+        let res = test::call_service(&app, post()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(!res.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER));
+        let res = test::call_service(&app, post()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            res.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(),
+            "true"
+        );
+    }
+}
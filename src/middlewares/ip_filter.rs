@@ -0,0 +1,194 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
+
+use crate::core::error::{Error as GatewayError, ErrorCode, ErrorKind};
+use crate::middlewares::metrics::{route_matches, route_of};
+use crate::utils::network::{client_ip, parse_network, IpBlocklist};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawIpRule {
+    routes: Vec<String>,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// Which clients may call some routes, by their networks.
+#[derive(Debug, Clone)]
+pub struct IpRule {
+    /// Route patterns such as `/metrics` or `/admin/*`.
+    pub routes: Vec<String>,
+    /// If not empty, clients outside of these are denied.
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpRule {
+    /// Parses a JSON array of rules, networks are in CIDR notation or bare
+    /// addresses.
+    pub fn parse_all(rules: &str) -> Result<Vec<Self>, String> {
+        let rules: Vec<RawIpRule> =
+            serde_json::from_str(rules).map_err(|e| e.to_string())?;
+        let networks = |networks: Vec<String>| {
+            networks
+                .iter()
+                .map(|network| parse_network(network))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        rules
+            .into_iter()
+            .map(|rule| {
+                Ok(Self {
+                    routes: rule.routes,
+                    allow: networks(rule.allow)?,
+                    deny: networks(rule.deny)?,
+                })
+            })
+            .collect()
+    }
+
+    fn admits(&self, ip: Option<IpAddr>) -> bool {
+        let within = |networks: &[IpNet], ip| {
+            networks.iter().any(|network| network.contains(&ip))
+        };
+        match ip {
+            Some(ip) => {
+                !within(&self.deny, ip)
+                    && (self.allow.is_empty() || within(&self.allow, ip))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+/// Denies clients blocked at runtime, if a blocklist is given, or by the
+/// rules matching the route, every matching rule must admit the client.
+/// Clients are identified by `client_ip`, so mind the trusted proxies.
+/// Blocks are not checked if the state backend fails.
+#[derive(Clone)]
+pub struct IpFilterMiddlewareFactory {
+    rules: Arc<Vec<IpRule>>,
+    blocklist: Option<IpBlocklist>,
+    scopes: &'static [&'static str],
+}
+
+impl IpFilterMiddlewareFactory {
+    pub fn new(
+        rules: Vec<IpRule>,
+        blocklist: Option<IpBlocklist>,
+        scopes: &'static [&'static str],
+    ) -> Self {
+        Self {
+            rules: Arc::new(rules),
+            blocklist,
+            scopes,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IpFilterMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IpFilterMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IpFilterMiddlewareService {
+            rules: self.rules.clone(),
+            blocklist: self.blocklist.clone(),
+            scopes: self.scopes,
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct IpFilterMiddlewareService<S> {
+    rules: Arc<Vec<IpRule>>,
+    blocklist: Option<IpBlocklist>,
+    scopes: &'static [&'static str],
+    service: Rc<S>,
+}
+
+impl<S> IpFilterMiddlewareService<S> {
+    fn admits(&self, req: &ServiceRequest, ip: Option<IpAddr>) -> bool {
+        let route = route_of(req.match_pattern(), req.path(), self.scopes);
+        self.rules
+            .iter()
+            .filter(|rule| {
+                rule.routes
+                    .iter()
+                    .any(|pattern| route_matches(pattern, &route))
+            })
+            .all(|rule| rule.admits(ip))
+    }
+}
+
+fn deny<B>(req: ServiceRequest) -> ServiceResponse<EitherBody<B>> {
+    req.error_response(
+        GatewayError::new(ErrorKind::Authorization, "ip denied")
+            .with_code(ErrorCode::IpDenied),
+    )
+    .map_into_right_body()
+}
+
+impl<S, B> Service<ServiceRequest> for IpFilterMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let ip = client_ip(req.request());
+        if !self.admits(&req, ip) {
+            return Box::pin(ready(Ok(deny(req))));
+        }
+        let (Some(blocklist), Some(ip)) = (self.blocklist.clone(), ip) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                fut.await.map(ServiceResponse::map_into_left_body)
+            });
+        };
+        let service = self.service.clone();
+        Box::pin(async move {
+            match blocklist.is_blocked(ip).await {
+                Ok(true) => return Ok(deny(req)),
+                Ok(false) => {}
+                Err(e) => log::warn!("failed to check ip blocks: {}", e),
+            }
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
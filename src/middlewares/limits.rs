@@ -0,0 +1,269 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::header::{CONTENT_LENGTH, CONTENT_TYPE},
+    Error, HttpMessage,
+};
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+use serde_json::json;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
+
+use crate::core::error::{Error as GatewayError, ErrorKind};
+use crate::middlewares::metrics::route_of;
+
+/// Allows request bodies of at most `limit` bytes to `route`, `*` meaning
+/// every route without a rule of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BodyLimitRule {
+    pub route: String,
+    pub limit: usize,
+}
+
+impl BodyLimitRule {
+    /// Parses comma separated `<route>=<bytes>` rules.
+    pub fn parse_all(rules: &str) -> Result<Vec<Self>, String> {
+        rules
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                rule.rsplit_once('=')
+                    .and_then(|(route, limit)| {
+                        Some(Self {
+                            route: route.trim().to_owned(),
+                            limit: limit.trim().parse().ok()?,
+                        })
+                    })
+                    .ok_or_else(|| format!("invalid body size limit {}", rule))
+            })
+            .collect()
+    }
+}
+
+/// Bounds of what a request may carry before it is routed any further.
+#[derive(Debug, Clone)]
+pub struct RequestLimits {
+    pub max_url_length: usize,
+    pub max_header_count: usize,
+    /// Names and values of all headers together.
+    pub max_header_bytes: usize,
+    pub body_limits: Vec<BodyLimitRule>,
+    /// The method and route of uploads, their multipart bodies are bounded
+    /// by `UploadLimit` instead.
+    pub upload_route: (&'static str, &'static str),
+}
+
+impl RequestLimits {
+    fn is_upload(&self, method: &str, route: &str) -> bool {
+        (method, route) == self.upload_route
+    }
+
+    fn body_limit(&self, route: &str) -> Option<usize> {
+        let limit = |route| {
+            self.body_limits
+                .iter()
+                .find(|rule| rule.route == route)
+                .map(|rule| rule.limit)
+        };
+        limit(route).or_else(|| limit("*"))
+    }
+
+    /// The largest body any route accepts, extractors must not be stricter.
+    pub fn largest_body_limit(&self) -> Option<usize> {
+        self.body_limits.iter().map(|rule| rule.limit).max()
+    }
+
+    fn check_head(&self, req: &ServiceRequest) -> Result<(), GatewayError> {
+        let url_length = req
+            .uri()
+            .path_and_query()
+            .map_or(0, |path_and_query| path_and_query.as_str().len());
+        if url_length > self.max_url_length {
+            return Err(GatewayError::new(
+                ErrorKind::UriTooLong,
+                format!("url exceeds {} bytes", self.max_url_length),
+            )
+            .with_details(json!({ "limit": self.max_url_length })));
+        }
+        if req.headers().len() > self.max_header_count {
+            return Err(GatewayError::new(
+                ErrorKind::HeadersTooLarge,
+                format!("more than {} headers", self.max_header_count),
+            )
+            .with_details(json!({ "limit": self.max_header_count })));
+        }
+        let header_bytes: usize = req
+            .headers()
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        if header_bytes > self.max_header_bytes {
+            return Err(GatewayError::new(
+                ErrorKind::HeadersTooLarge,
+                format!("headers exceed {} bytes", self.max_header_bytes),
+            )
+            .with_details(json!({ "limit": self.max_header_bytes })));
+        }
+        Ok(())
+    }
+}
+
+fn body_too_large(limit: usize) -> GatewayError {
+    GatewayError::new(
+        ErrorKind::PayloadTooLarge,
+        format!("payload exceeds the limit of {} bytes", limit),
+    )
+    .with_details(json!({ "limit": limit }))
+}
+
+/// Rejects requests exceeding `RequestLimits` before auth or any upstream
+/// is involved. Bodies declaring a larger `Content-Length` are rejected
+/// right away, others are cut off once they grow past the limit of their
+/// route. Multipart bodies to the upload route are bounded by `UploadLimit`
+/// instead.
+#[derive(Clone)]
+pub struct RequestLimitMiddlewareFactory {
+    limits: Arc<RequestLimits>,
+    scopes: &'static [&'static str],
+}
+
+impl RequestLimitMiddlewareFactory {
+    pub fn new(limits: RequestLimits, scopes: &'static [&'static str]) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            scopes,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestLimitMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestLimitMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestLimitMiddlewareService {
+            limits: self.limits.clone(),
+            scopes: self.scopes,
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestLimitMiddlewareService<S> {
+    limits: Arc<RequestLimits>,
+    scopes: &'static [&'static str],
+    service: Rc<S>,
+}
+
+impl<S> RequestLimitMiddlewareService<S> {
+    /// The body limit of the request, multipart uploads have none here.
+    fn body_limit(&self, req: &ServiceRequest) -> Option<usize> {
+        let route = route_of(req.match_pattern(), req.path(), self.scopes);
+        let multipart = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("multipart/"));
+        if multipart && self.limits.is_upload(req.method().as_str(), &route) {
+            return None;
+        }
+        self.limits.body_limit(&route)
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequestLimitMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let reject = |req: ServiceRequest, e| {
+            let res = req.error_response(e).map_into_right_body();
+            Box::pin(ready(Ok(res))) as Self::Future
+        };
+        if let Err(e) = self.limits.check_head(&req) {
+            return reject(req, e);
+        }
+        if let Some(limit) = self.body_limit(&req) {
+            let content_length = req
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<usize>().ok());
+            if content_length.is_some_and(|length| length > limit) {
+                return reject(req, body_too_large(limit));
+            }
+            let mut received = 0;
+            let payload = req.take_payload().map(move |chunk| {
+                let chunk = chunk?;
+                received += chunk.len();
+                if received > limit {
+                    return Err(PayloadError::Overflow);
+                }
+                Ok(chunk)
+            });
+            req.set_payload(Payload::Stream {
+                payload: payload.boxed_local(),
+            });
+        }
+        let fut = self.service.call(req);
+        Box::pin(
+            async move { fut.await.map(ServiceResponse::map_into_left_body) },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_limits_of_routes_override_the_default() {
+        let limits = RequestLimits {
+            max_url_length: 0,
+            max_header_count: 0,
+            max_header_bytes: 0,
+            body_limits: BodyLimitRule::parse_all(
+                "*=1024, /apis/dogs/{dog_id}/portrait=4096",
+            )
+            .unwrap(),
+            upload_route: ("POST", "/apis/uploads"),
+        };
+        assert!(limits.is_upload("POST", "/apis/uploads"));
+        assert!(!limits.is_upload("PUT", "/apis/uploads"));
+        assert!(!limits.is_upload("POST", "/apis/uploads/*"));
+        assert_eq!(
+            limits.body_limit("/apis/dogs/{dog_id}/portrait"),
+            Some(4096)
+        );
+        assert_eq!(limits.body_limit("/apis/breeds/*"), Some(1024));
+        assert_eq!(limits.largest_body_limit(), Some(4096));
+        assert!(BodyLimitRule::parse_all("*=1k").is_err());
+        assert!(BodyLimitRule::parse_all("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::task::Poll;
+use std::time::Instant;
+
+use crate::utils::metrics::Metrics;
+
+/// Counts inbound requests and measures how long they take, labelled by the
+/// matched route rather than the path to keep the number of series bounded.
+/// Requests served by the default service of a scope match no route, they
+/// are labelled by the first of `scopes` prefixing their path.
+#[derive(Clone)]
+pub struct MetricsMiddlewareFactory {
+    metrics: Metrics,
+    scopes: &'static [&'static str],
+}
+
+impl MetricsMiddlewareFactory {
+    pub fn new(metrics: Metrics, scopes: &'static [&'static str]) -> Self {
+        Self { metrics, scopes }
+    }
+}
+
+/// The route label of a request, `pattern` is the route it matched if any.
+pub(crate) fn route_of(
+    pattern: Option<String>,
+    path: &str,
+    scopes: &[&str],
+) -> String {
+    pattern
+        .or_else(|| {
+            scopes
+                .iter()
+                .find(|scope| {
+                    path.strip_prefix(*scope).is_some_and(|rest| {
+                        rest.is_empty() || rest.starts_with('/')
+                    })
+                })
+                .map(|scope| format!("{}/*", scope))
+        })
+        .unwrap_or_else(|| "unmatched".to_owned())
+}
+
+/// Whether a route label is matched by `pattern`, which is a label, a
+/// prefix followed by `/*` to match every route below it, or `*`.
+pub(crate) fn route_matches(pattern: &str, route: &str) -> bool {
+    pattern == "*"
+        || pattern == route
+        || pattern.strip_suffix("/*").is_some_and(|prefix| {
+            route
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+}
+
+impl<S, B> Transform<S, ServiceRequest> for MetricsMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MetricsMiddlewareService {
+            metrics: self.metrics.clone(),
+            scopes: self.scopes,
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct MetricsMiddlewareService<S> {
+    metrics: Metrics,
+    scopes: &'static [&'static str],
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for MetricsMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let path = req.path().to_owned();
+        let scopes = self.scopes;
+        let metrics = self.metrics.clone();
+        metrics.request_started();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let result = fut.await;
+            let (route, status) = match &result {
+                Ok(res) => (res.request().match_pattern(), res.status()),
+                Err(e) => (None, e.as_response_error().status_code()),
+            };
+            metrics.request_finished(
+                &method,
+                &route_of(route, &path, scopes),
+                status.as_u16(),
+                started.elapsed(),
+            );
+            result
+        })
+    }
+}
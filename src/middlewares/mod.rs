@@ -1 +1,12 @@
+pub mod access_log;
 pub mod auth;
+pub mod cors;
+pub mod error;
+pub mod idempotency;
+pub mod ip_filter;
+pub mod limits;
+pub mod metrics;
+pub mod rate_limit;
+pub mod request_id;
+pub mod security_headers;
+pub mod tracing;
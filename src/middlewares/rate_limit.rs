@@ -0,0 +1,307 @@
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
+    Error, HttpMessage,
+};
+use futures::future::LocalBoxFuture;
+use std::collections::HashSet;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use crate::core::error::{Error as GatewayError, ErrorKind};
+use crate::middlewares::auth::AuthenticatedUser;
+use crate::middlewares::metrics::route_of;
+use crate::state::{StateBackend, StateStore, Throttle};
+use crate::utils::metrics::Metrics;
+use crate::utils::network::client_ip;
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// What requests are counted by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitKey {
+    /// The verified user, or the client IP for anonymous requests.
+    User,
+    Ip,
+    /// The `X-API-Key` header if it is a known key, or the client IP.
+    ApiKey,
+}
+
+impl RateLimitKey {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Ip => "ip",
+            Self::ApiKey => "api_key",
+        }
+    }
+}
+
+/// Allows `capacity` requests per `period` to `route`, `*` meaning every
+/// route, for each key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitRule {
+    pub route: String,
+    pub capacity: u32,
+    pub period: Duration,
+    pub key: RateLimitKey,
+}
+
+impl RateLimitRule {
+    /// Parses comma separated `<route>=<requests>/<seconds>[:<key>]` rules,
+    /// where key is one of user (the default), ip or api_key.
+    pub fn parse_all(rules: &str) -> Result<Vec<Self>, String> {
+        rules
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                Self::parse(rule)
+                    .ok_or_else(|| format!("invalid rate limit {}", rule))
+            })
+            .collect()
+    }
+
+    fn parse(rule: &str) -> Option<Self> {
+        let (route, limit) = rule.rsplit_once('=')?;
+        let (limit, key) = match limit.split_once(':') {
+            Some((limit, key)) => (limit, key),
+            None => (limit, "user"),
+        };
+        let (capacity, period) = limit.split_once('/')?;
+        let capacity = capacity.parse().ok().filter(|c| *c > 0)?;
+        let period = period.parse().ok().filter(|p| *p > 0)?;
+        let key = match key {
+            "user" => RateLimitKey::User,
+            "ip" => RateLimitKey::Ip,
+            "api_key" => RateLimitKey::ApiKey,
+            _ => return None,
+        };
+        Some(Self {
+            route: route.trim().to_owned(),
+            capacity,
+            period: Duration::from_secs(period),
+            key,
+        })
+    }
+}
+
+/// Throttles requests with token buckets, rejected ones get a 429 error.
+/// Responses carry the `RateLimit-*` headers of the most exhausted bucket.
+/// Wrap user keyed rules inside auth so that verified users are known, and
+/// the others outside so that requests auth rejects are counted too.
+/// Requests are let through, and counted in metrics, if the state backend
+/// fails.
+#[derive(Clone)]
+pub struct RateLimitMiddlewareFactory {
+    rules: Arc<Vec<RateLimitRule>>,
+    api_keys: Arc<HashSet<String>>,
+    state: StateBackend,
+    metrics: Metrics,
+    scopes: &'static [&'static str],
+}
+
+impl RateLimitMiddlewareFactory {
+    /// Only `api_keys` get buckets of their own, requests with other keys
+    /// are counted by client IP.
+    pub fn new(
+        rules: Vec<RateLimitRule>,
+        api_keys: HashSet<String>,
+        state: StateBackend,
+        metrics: Metrics,
+        scopes: &'static [&'static str],
+    ) -> Self {
+        Self {
+            rules: Arc::new(rules),
+            api_keys: Arc::new(api_keys),
+            state,
+            metrics,
+            scopes,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimitMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddlewareService {
+            rules: self.rules.clone(),
+            api_keys: self.api_keys.clone(),
+            state: self.state.clone(),
+            metrics: self.metrics.clone(),
+            scopes: self.scopes,
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RateLimitMiddlewareService<S> {
+    rules: Arc<Vec<RateLimitRule>>,
+    api_keys: Arc<HashSet<String>>,
+    state: StateBackend,
+    metrics: Metrics,
+    scopes: &'static [&'static str],
+    service: Rc<S>,
+}
+
+impl<S> RateLimitMiddlewareService<S> {
+    fn client_key(&self, req: &ServiceRequest, key: RateLimitKey) -> String {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty())
+        };
+        let keyed = match key {
+            RateLimitKey::User => req
+                .extensions()
+                .get::<AuthenticatedUser>()
+                .map(|user| format!("user:{}", user.0)),
+            RateLimitKey::Ip => None,
+            RateLimitKey::ApiKey => header(API_KEY_HEADER)
+                .filter(|key| self.api_keys.contains(*key))
+                .map(|key| format!("api_key:{}", key)),
+        };
+        keyed.unwrap_or_else(|| match client_ip(req.request()) {
+            Some(ip) => format!("ip:{}", ip),
+            None => "ip:unknown".to_owned(),
+        })
+    }
+
+    /// The buckets the request falls in, with their capacity and period.
+    fn buckets(&self, req: &ServiceRequest) -> Vec<(String, u32, Duration)> {
+        let route = route_of(req.match_pattern(), req.path(), self.scopes);
+        self.rules
+            .iter()
+            .filter(|rule| rule.route == "*" || rule.route == route)
+            .map(|rule| {
+                let key = format!(
+                    "rate_limit:{}:{}:{}",
+                    rule.route,
+                    rule.key.as_str(),
+                    self.client_key(req, rule.key)
+                );
+                (key, rule.capacity, rule.period)
+            })
+            .collect()
+    }
+}
+
+/// Takes a token from every bucket, the returned throttle is the most
+/// restrictive one.
+async fn throttle(
+    state: &StateBackend,
+    metrics: &Metrics,
+    buckets: Vec<(String, u32, Duration)>,
+) -> Option<Throttle> {
+    let mut strictest: Option<Throttle> = None;
+    for (key, capacity, period) in buckets {
+        match state.take_token(&key, capacity, period).await {
+            Ok(throttle) => {
+                if strictest.is_none_or(|strictest| {
+                    (throttle.allowed, throttle.remaining)
+                        < (strictest.allowed, strictest.remaining)
+                }) {
+                    strictest = Some(throttle);
+                }
+            }
+            Err(e) => {
+                log::warn!("failed to take a rate limit token: {}", e);
+                metrics.rate_limit_store_failed();
+            }
+        }
+    }
+    strictest
+}
+
+fn insert_header(headers: &mut HeaderMap, name: &'static str, value: u64) {
+    headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+}
+
+/// Whole seconds, rounded up so that clients never retry too early.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Sets the headers of `throttle`, unless an inner limiter already set
+/// those of a more exhausted bucket.
+fn set_headers(headers: &mut HeaderMap, throttle: &Throttle) {
+    let stricter = headers
+        .get("ratelimit-remaining")
+        .and_then(|v| v.to_str().ok()?.parse::<u32>().ok())
+        .is_some_and(|remaining| remaining < throttle.remaining);
+    if stricter && throttle.allowed {
+        return;
+    }
+    insert_header(headers, "ratelimit-limit", throttle.limit.into());
+    insert_header(headers, "ratelimit-remaining", throttle.remaining.into());
+    insert_header(headers, "ratelimit-reset", ceil_secs(throttle.reset));
+    if !throttle.allowed {
+        headers.insert(RETRY_AFTER, ceil_secs(throttle.retry_after).into());
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let buckets = self.buckets(&req);
+        if buckets.is_empty() {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                fut.await.map(ServiceResponse::map_into_boxed_body)
+            });
+        }
+        let state = self.state.clone();
+        let metrics = self.metrics.clone();
+        let service = self.service.clone();
+        Box::pin(async move {
+            let Some(throttle) = throttle(&state, &metrics, buckets).await
+            else {
+                return service
+                    .call(req)
+                    .await
+                    .map(|res| res.map_into_boxed_body());
+            };
+            if !throttle.allowed {
+                let mut res = req.error_response(GatewayError::new(
+                    ErrorKind::RateLimited,
+                    "rate limit exceeded",
+                ));
+                set_headers(res.headers_mut(), &throttle);
+                return Ok(res);
+            }
+            let mut res = service.call(req).await?;
+            set_headers(res.headers_mut(), &throttle);
+            Ok(res.map_into_boxed_body())
+        })
+    }
+}
@@ -0,0 +1,86 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::task::Poll;
+
+use crate::utils::request_id::{self, REQUEST_ID_HEADER};
+
+/// Accepts the `X-Request-ID` of the caller or generates one, makes it the
+/// current request id for upstream calls and echoes it in the response.
+#[derive(Clone, Default)]
+pub struct RequestIdMiddlewareFactory;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIdMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestIdMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| request_id::is_valid(id))
+            .map_or_else(request_id::generate, str::to_owned);
+        let name = HeaderName::from_static("x-request-id");
+        // Only valid ids are accepted, so the value is always a valid header.
+        let value = HeaderValue::from_str(&id).unwrap();
+        req.headers_mut().insert(name.clone(), value.clone());
+        let service = self.service.clone();
+        let fut = request_id::scope(id, move || service.call(req));
+        Box::pin(async move {
+            match fut.await {
+                Ok(mut res) => {
+                    res.headers_mut().insert(name, value);
+                    Ok(res)
+                }
+                Err(e) => {
+                    let mut resp = e.as_response_error().error_response();
+                    resp.headers_mut().insert(name, value);
+                    Err(InternalError::from_response(e, resp).into())
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,141 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_SECURITY_POLICY,
+        CONTENT_TYPE, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+        X_CONTENT_TYPE_OPTIONS,
+    },
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
+
+/// Headers added to responses, empty ones are left out.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeaders {
+    pub strict_transport_security: String,
+    pub x_content_type_options: String,
+    pub referrer_policy: String,
+    /// Only sent along with HTML pages.
+    pub content_security_policy: String,
+}
+
+struct Headers {
+    all: Vec<(HeaderName, HeaderValue)>,
+    html: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Headers {
+    fn insert(&self, headers: &mut HeaderMap) {
+        let html = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/html"));
+        let html = self.html.iter().filter(|_| html);
+        for (name, value) in self.all.iter().chain(html) {
+            if !headers.contains_key(name) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Adds security headers to every response, error responses included,
+/// unless upstreams set them already.
+#[derive(Clone)]
+pub struct SecurityHeadersMiddlewareFactory {
+    headers: Arc<Headers>,
+}
+
+impl SecurityHeadersMiddlewareFactory {
+    pub fn new(headers: SecurityHeaders) -> Result<Self, String> {
+        let parse = |name: HeaderName, value: String| {
+            if value.is_empty() {
+                return Ok(None);
+            }
+            HeaderValue::try_from(value)
+                .map(|value| Some((name.clone(), value)))
+                .map_err(|_| format!("invalid {} header", name))
+        };
+        let all = [
+            (STRICT_TRANSPORT_SECURITY, headers.strict_transport_security),
+            (X_CONTENT_TYPE_OPTIONS, headers.x_content_type_options),
+            (REFERRER_POLICY, headers.referrer_policy),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| parse(name, value).transpose())
+        .collect::<Result<_, _>>()?;
+        let html =
+            parse(CONTENT_SECURITY_POLICY, headers.content_security_policy)?
+                .into_iter()
+                .collect();
+        Ok(Self {
+            headers: Arc::new(Headers { all, html }),
+        })
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeadersMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddlewareService {
+            headers: self.headers.clone(),
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct SecurityHeadersMiddlewareService<S> {
+    headers: Arc<Headers>,
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let headers = self.headers.clone();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            match fut.await {
+                Ok(mut res) => {
+                    headers.insert(res.headers_mut());
+                    Ok(res)
+                }
+                Err(e) => {
+                    let mut res = e.error_response();
+                    headers.insert(res.headers_mut());
+                    Err(InternalError::from_response(e, res).into())
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,134 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use opentelemetry::trace::{FutureExt, Status, TraceContextExt};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::trace::Tracer;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
+
+use crate::utils::redact::Redactor;
+use crate::utils::request_id::REQUEST_ID_HEADER;
+use crate::utils::telemetry;
+
+/// Opens a server span for every inbound request, upstream calls made while
+/// serving it become its children.
+#[derive(Clone)]
+pub struct TracingMiddlewareFactory {
+    tracer: Tracer,
+    redactor: Arc<Redactor>,
+}
+
+impl TracingMiddlewareFactory {
+    pub fn new(tracer: Tracer, redactor: Arc<Redactor>) -> Self {
+        Self { tracer, redactor }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for TracingMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TracingMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TracingMiddlewareService {
+            tracer: self.tracer.clone(),
+            redactor: self.redactor.clone(),
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct TracingMiddlewareService<S> {
+    tracer: Tracer,
+    redactor: Arc<Redactor>,
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for TracingMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let mut attributes = vec![
+            KeyValue::new("http.request.method", req.method().to_string()),
+            KeyValue::new(
+                "url.path",
+                self.redactor.redact(req.path()).into_owned(),
+            ),
+        ];
+        if let Some(id) = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+        {
+            attributes.push(KeyValue::new("request_id", id.to_owned()));
+        }
+        let cx = telemetry::start_request(
+            &self.tracer,
+            &self.redactor,
+            req.headers(),
+            req.method().to_string(),
+            attributes,
+        );
+        let fut = {
+            let _guard = cx.clone().attach();
+            self.service.call(req)
+        };
+        Box::pin(async move {
+            let result = fut.with_context(cx.clone()).await;
+            let span = cx.span();
+            let status = match &result {
+                Ok(res) => {
+                    // The route is only known once the request was routed.
+                    if let Some(pattern) = res.request().match_pattern() {
+                        span.update_name(format!(
+                            "{} {}",
+                            res.request().method(),
+                            pattern
+                        ));
+                        span.set_attribute(KeyValue::new(
+                            "http.route",
+                            pattern,
+                        ));
+                    }
+                    res.status()
+                }
+                Err(e) => e.as_response_error().status_code(),
+            };
+            span.set_attribute(KeyValue::new(
+                "http.response.status_code",
+                i64::from(status.as_u16()),
+            ));
+            if status.is_server_error() {
+                span.set_status(Status::error(status.to_string()));
+            }
+            span.end();
+            result
+        })
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{StateStore, Throttle};
+use crate::core::error::Error;
+
+struct Bucket {
+    tokens: f64,
+    capacity: u32,
+    period: Duration,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let rate = f64::from(self.capacity) / self.period.as_secs_f64();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * rate).min(f64::from(self.capacity));
+        self.updated = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= f64::from(self.capacity)
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    buckets: HashMap<String, Bucket>,
+    /// The number of buckets at which full ones are dropped next.
+    sweep_buckets_at: usize,
+    /// Values and when they expire.
+    counters: HashMap<String, (u32, Instant)>,
+    /// Values and when they expire, if ever.
+    values: HashMap<String, (String, Option<Instant>)>,
+}
+
+/// Full buckets are dropped once there are at least this many.
+const BUCKETS_SWEEP_THRESHOLD: usize = 1024;
+
+/// Keeps state in the process, replicas do not share it.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        period: Duration,
+    ) -> Result<Throttle, Error> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let Entries {
+            buckets,
+            sweep_buckets_at,
+            ..
+        } = &mut *entries;
+        if buckets.len() >= (*sweep_buckets_at).max(BUCKETS_SWEEP_THRESHOLD) {
+            buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                !bucket.is_full()
+            });
+            *sweep_buckets_at = buckets.len() * 2;
+        }
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: f64::from(capacity),
+            capacity,
+            period,
+            updated: now,
+        });
+        bucket.refill(now);
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        Ok(Throttle::new(allowed, bucket.tokens, capacity, period))
+    }
+
+    async fn increment(
+        &self,
+        key: &str,
+        window: Duration,
+    ) -> Result<u32, Error> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.counters.retain(|_, (_, expires)| *expires > now);
+        let (count, _) = entries
+            .counters
+            .entry(key.to_owned())
+            .or_insert((0, now + window));
+        *count += 1;
+        Ok(*count)
+    }
+
+    async fn decrement(&self, key: &str) -> Result<(), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some((count, _)) = entries.counters.get_mut(key) {
+            *count = count.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    async fn count(&self, key: &str) -> Result<u32, Error> {
+        let entries = self.entries.lock().unwrap();
+        Ok(match entries.counters.get(key) {
+            Some((count, expires)) if *expires > Instant::now() => *count,
+            _ => 0,
+        })
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.counters.remove(key);
+        entries.values.remove(key);
+        Ok(())
+    }
+
+    async fn insert_if_absent(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.counters.retain(|_, (_, expires)| *expires > now);
+        if entries.counters.contains_key(key) {
+            return Ok(false);
+        }
+        entries.counters.insert(key.to_owned(), (1, now + ttl));
+        Ok(true)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let entries = self.entries.lock().unwrap();
+        Ok(match entries.values.get(key) {
+            Some((value, expires))
+                if expires.is_none_or(|at| at > Instant::now()) =>
+            {
+                Some(value.clone())
+            }
+            _ => None,
+        })
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), Error> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .values
+            .retain(|_, (_, expires)| expires.is_none_or(|at| at > now));
+        entries.values.insert(
+            key.to_owned(),
+            (value.to_owned(), ttl.map(|ttl| now + ttl)),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn token_buckets_refill_over_the_period() {
+        let store = MemoryStore::new();
+        let period = Duration::from_millis(200);
+        for remaining in [1, 0] {
+            let throttle = store.take_token("a", 2, period).await.unwrap();
+            assert!(throttle.allowed);
+            assert_eq!(throttle.remaining, remaining);
+        }
+        let throttle = store.take_token("a", 2, period).await.unwrap();
+        assert!(!throttle.allowed);
+        assert!(throttle.retry_after > Duration::ZERO);
+        assert!(throttle.retry_after <= Duration::from_millis(100));
+        assert!(store.take_token("b", 2, period).await.unwrap().allowed);
+        std::thread::sleep(throttle.retry_after);
+        assert!(store.take_token("a", 2, period).await.unwrap().allowed);
+    }
+
+    #[actix_web::test]
+    async fn counters_expire_after_the_window() {
+        let store = MemoryStore::new();
+        let window = Duration::from_millis(50);
+        assert_eq!(store.increment("a", window).await.unwrap(), 1);
+        assert_eq!(store.increment("a", window).await.unwrap(), 2);
+        store.decrement("a").await.unwrap();
+        assert_eq!(store.count("a").await.unwrap(), 1);
+        assert!(!store.insert_if_absent("a", window).await.unwrap());
+        std::thread::sleep(window);
+        assert_eq!(store.count("a").await.unwrap(), 0);
+        assert!(store.insert_if_absent("a", window).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn values_expire_after_their_ttl() {
+        let store = MemoryStore::new();
+        let ttl = Duration::from_millis(50);
+        assert_eq!(store.get("a").await.unwrap(), None);
+        store.set("a", "1", None).await.unwrap();
+        store.set("a", "2", Some(ttl)).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap().as_deref(), Some("2"));
+        store.remove("a").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), None);
+        store.set("a", "3", Some(ttl)).await.unwrap();
+        store.set("b", "4", None).await.unwrap();
+        std::thread::sleep(ttl);
+        assert_eq!(store.get("a").await.unwrap(), None);
+        assert_eq!(store.get("b").await.unwrap().as_deref(), Some("4"));
+    }
+}
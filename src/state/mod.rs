@@ -0,0 +1,196 @@
+//! State that has to be shared by gateway replicas, such as rate limits,
+//! guards against brute force, cached tokens and idempotency records, kept
+//! in memory or in Redis.
+
+use std::time::Duration;
+
+use crate::core::error::Error;
+
+pub mod memory;
+pub mod redis;
+
+pub use self::memory::MemoryStore;
+pub use self::redis::RedisStore;
+
+/// The outcome of taking a token from a bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throttle {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Until the bucket is full again.
+    pub reset: Duration,
+    /// Until a token is available, zero if one was taken.
+    pub retry_after: Duration,
+}
+
+impl Throttle {
+    /// `tokens` are those left in a bucket refilling `capacity` tokens
+    /// evenly over `period`.
+    fn new(
+        allowed: bool,
+        tokens: f64,
+        capacity: u32,
+        period: Duration,
+    ) -> Self {
+        let rate = f64::from(capacity) / period.as_secs_f64();
+        Self {
+            allowed,
+            limit: capacity,
+            remaining: tokens as u32,
+            reset: Duration::from_secs_f64(
+                (f64::from(capacity) - tokens).max(0.0) / rate,
+            ),
+            retry_after: if allowed {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64((1.0 - tokens).max(0.0) / rate)
+            },
+        }
+    }
+}
+
+pub trait StateStore: Clone + 'static {
+    /// Takes a token from the bucket at `key`, which holds up to `capacity`
+    /// tokens and refills them evenly over `period`.
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        period: Duration,
+    ) -> Result<Throttle, Error>;
+    /// Increments the counter at `key` and returns its value, the counter is
+    /// removed once `window` has elapsed since it was created.
+    async fn increment(
+        &self,
+        key: &str,
+        window: Duration,
+    ) -> Result<u32, Error>;
+    /// Decrements the counter at `key` if it exists and is above zero, its
+    /// window is kept.
+    async fn decrement(&self, key: &str) -> Result<(), Error>;
+    async fn count(&self, key: &str) -> Result<u32, Error>;
+    async fn remove(&self, key: &str) -> Result<(), Error>;
+    /// Marks `key` as present for `ttl`, returns `false` if it already was.
+    async fn insert_if_absent(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error>;
+    /// The value `set` at `key`, unless it has expired.
+    async fn get(&self, key: &str) -> Result<Option<String>, Error>;
+    /// Stores `value` at `key` for `ttl`, or until removed if `None`,
+    /// replacing any previous one.
+    async fn set(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), Error>;
+}
+
+/// The store selected by config.
+#[derive(Clone)]
+pub enum StateBackend {
+    Memory(MemoryStore),
+    Redis(Box<RedisStore>),
+}
+
+impl StateBackend {
+    pub fn memory() -> Self {
+        Self::Memory(MemoryStore::new())
+    }
+
+    /// `url` is `memory`, or a `redis://` or `rediss://` URL. Keys in Redis
+    /// start with `key_prefix`, commands fail after `timeout`.
+    pub fn from_url(
+        url: &str,
+        key_prefix: &str,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        if url == "memory" {
+            return Ok(Self::memory());
+        }
+        RedisStore::new(url, key_prefix, timeout)
+            .map(|store| Self::Redis(Box::new(store)))
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl StateStore for StateBackend {
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        period: Duration,
+    ) -> Result<Throttle, Error> {
+        match self {
+            Self::Memory(store) => {
+                store.take_token(key, capacity, period).await
+            }
+            Self::Redis(store) => store.take_token(key, capacity, period).await,
+        }
+    }
+
+    async fn increment(
+        &self,
+        key: &str,
+        window: Duration,
+    ) -> Result<u32, Error> {
+        match self {
+            Self::Memory(store) => store.increment(key, window).await,
+            Self::Redis(store) => store.increment(key, window).await,
+        }
+    }
+
+    async fn decrement(&self, key: &str) -> Result<(), Error> {
+        match self {
+            Self::Memory(store) => store.decrement(key).await,
+            Self::Redis(store) => store.decrement(key).await,
+        }
+    }
+
+    async fn count(&self, key: &str) -> Result<u32, Error> {
+        match self {
+            Self::Memory(store) => store.count(key).await,
+            Self::Redis(store) => store.count(key).await,
+        }
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), Error> {
+        match self {
+            Self::Memory(store) => store.remove(key).await,
+            Self::Redis(store) => store.remove(key).await,
+        }
+    }
+
+    async fn insert_if_absent(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        match self {
+            Self::Memory(store) => store.insert_if_absent(key, ttl).await,
+            Self::Redis(store) => store.insert_if_absent(key, ttl).await,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        match self {
+            Self::Memory(store) => store.get(key).await,
+            Self::Redis(store) => store.get(key).await,
+        }
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), Error> {
+        match self {
+            Self::Memory(store) => store.set(key, value, ttl).await,
+            Self::Redis(store) => store.set(key, value, ttl).await,
+        }
+    }
+}
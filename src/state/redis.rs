@@ -0,0 +1,250 @@
+use actix_web::rt::time::timeout;
+use futures::lock::Mutex;
+use futures::Future;
+use redis::aio::ConnectionManager;
+use redis::{Client, RedisError, Script};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{StateStore, Throttle};
+use crate::core::error::{Error, ErrorKind};
+
+/// Refills the bucket by the time elapsed on the Redis clock, so that
+/// replicas with skewed clocks agree. Returns whether a token was taken and
+/// the tokens left, as a string since Lua numbers become integers.
+const TAKE_TOKEN: &str = r"
+local capacity = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * capacity / period)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], period)
+return {allowed, tostring(tokens)}
+";
+
+const INCREMENT: &str = r"
+local count = redis.call('INCR', KEYS[1])
+if count == 1 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[1])
+end
+return count
+";
+
+/// Leaves missing counters alone, lest they come back without an expiry.
+const DECREMENT: &str = r"
+local count = tonumber(redis.call('GET', KEYS[1]))
+if count and count > 0 then
+    redis.call('DECR', KEYS[1])
+end
+return 0
+";
+
+/// Keeps state in Redis, or anything speaking its protocol, so that every
+/// replica sees the same. Connects on first use and reconnects as needed.
+#[derive(Clone)]
+pub struct RedisStore {
+    client: Client,
+    connection: Arc<Mutex<Option<ConnectionManager>>>,
+    key_prefix: Arc<str>,
+    timeout: Duration,
+    take_token: Script,
+    increment: Script,
+    decrement: Script,
+}
+
+fn unavailable(e: RedisError) -> Error {
+    Error::wrap(ErrorKind::UpstreamUnavailable)(e).with_upstream("state")
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX).max(1)
+}
+
+impl RedisStore {
+    pub fn new(
+        url: &str,
+        key_prefix: &str,
+        timeout: Duration,
+    ) -> Result<Self, RedisError> {
+        Ok(Self {
+            client: Client::open(url)?,
+            connection: Arc::new(Mutex::new(None)),
+            key_prefix: key_prefix.into(),
+            timeout,
+            take_token: Script::new(TAKE_TOKEN),
+            increment: Script::new(INCREMENT),
+            decrement: Script::new(DECREMENT),
+        })
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.key_prefix, key)
+    }
+
+    async fn connect(&self) -> Result<ConnectionManager, RedisError> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = connection.as_ref() {
+            return Ok(connection.clone());
+        }
+        // Commands time out quickly anyway, retry once rather than backing
+        // off for seconds.
+        let manager =
+            ConnectionManager::new_with_backoff(self.client.clone(), 2, 100, 1)
+                .await?;
+        *connection = Some(manager.clone());
+        Ok(manager)
+    }
+
+    /// Runs `command` on the shared connection, giving up after the timeout.
+    async fn run<T, F, Fut>(&self, command: F) -> Result<T, Error>
+    where
+        F: FnOnce(ConnectionManager) -> Fut,
+        Fut: Future<Output = Result<T, RedisError>>,
+    {
+        let result = timeout(self.timeout, async {
+            command(self.connect().await?).await
+        })
+        .await;
+        match result {
+            Ok(result) => result.map_err(unavailable),
+            Err(_) => Err(Error::new(
+                ErrorKind::UpstreamTimeout,
+                "state backend timed out",
+            )
+            .with_upstream("state")),
+        }
+    }
+}
+
+impl StateStore for RedisStore {
+    async fn take_token(
+        &self,
+        key: &str,
+        capacity: u32,
+        period: Duration,
+    ) -> Result<Throttle, Error> {
+        let key = self.key(key);
+        let (allowed, tokens): (i64, String) = self
+            .run(|mut connection| async move {
+                self.take_token
+                    .key(key)
+                    .arg(capacity)
+                    .arg(millis(period))
+                    .invoke_async(&mut connection)
+                    .await
+            })
+            .await?;
+        let tokens = tokens.parse().map_err(|_| {
+            Error::new(ErrorKind::UpstreamError, "invalid bucket state")
+                .with_upstream("state")
+        })?;
+        Ok(Throttle::new(allowed == 1, tokens, capacity, period))
+    }
+
+    async fn increment(
+        &self,
+        key: &str,
+        window: Duration,
+    ) -> Result<u32, Error> {
+        let key = self.key(key);
+        self.run(|mut connection| async move {
+            self.increment
+                .key(key)
+                .arg(millis(window))
+                .invoke_async(&mut connection)
+                .await
+        })
+        .await
+    }
+
+    async fn decrement(&self, key: &str) -> Result<(), Error> {
+        let key = self.key(key);
+        self.run(|mut connection| async move {
+            self.decrement.key(key).invoke_async(&mut connection).await
+        })
+        .await
+    }
+
+    async fn count(&self, key: &str) -> Result<u32, Error> {
+        let key = self.key(key);
+        let count: Option<u32> = self
+            .run(|mut connection| async move {
+                redis::cmd("GET")
+                    .arg(key)
+                    .query_async(&mut connection)
+                    .await
+            })
+            .await?;
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), Error> {
+        let key = self.key(key);
+        self.run(|mut connection| async move {
+            redis::cmd("DEL")
+                .arg(key)
+                .query_async(&mut connection)
+                .await
+        })
+        .await
+    }
+
+    async fn insert_if_absent(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        let key = self.key(key);
+        let inserted: Option<String> = self
+            .run(|mut connection| async move {
+                redis::cmd("SET")
+                    .arg(key)
+                    .arg(1)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(millis(ttl))
+                    .query_async(&mut connection)
+                    .await
+            })
+            .await?;
+        Ok(inserted.is_some())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let key = self.key(key);
+        self.run(|mut connection| async move {
+            redis::cmd("GET")
+                .arg(key)
+                .query_async(&mut connection)
+                .await
+        })
+        .await
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), Error> {
+        let key = self.key(key);
+        self.run(|mut connection| async move {
+            let mut command = redis::cmd("SET");
+            command.arg(key).arg(value);
+            if let Some(ttl) = ttl {
+                command.arg("PX").arg(millis(ttl));
+            }
+            command.query_async(&mut connection).await
+        })
+        .await
+    }
+}
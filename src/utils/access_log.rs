@@ -0,0 +1,39 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The log target of access log lines, in JSON mode they are written as is.
+pub const ACCESS_LOG_TARGET: &str = "little-walk-api-gateway::access";
+
+/// An upstream call made while serving a request.
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamCall {
+    pub name: &'static str,
+    pub latency: Duration,
+}
+
+tokio::task_local! {
+    static LAST_UPSTREAM_CALL: Rc<Cell<Option<UpstreamCall>>>;
+}
+
+/// Remembers the call as the last upstream call of the request being served
+/// by the current task, if any.
+pub fn record_upstream_call(name: &'static str, latency: Duration) {
+    let _ = LAST_UPSTREAM_CALL
+        .try_with(|call| call.set(Some(UpstreamCall { name, latency })));
+}
+
+/// Calls `f` and polls the future it returns with `call` receiving the
+/// upstream calls recorded meanwhile.
+pub fn scope<F, Fut>(
+    call: Rc<Cell<Option<UpstreamCall>>>,
+    f: F,
+) -> impl Future<Output = Fut::Output>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future,
+{
+    let fut = LAST_UPSTREAM_CALL.sync_scope(call.clone(), f);
+    LAST_UPSTREAM_CALL.scope(call, fut)
+}
@@ -0,0 +1,178 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    /// Calls fail fast until the cool down is over.
+    Open,
+    /// The cool down is over, a single trial call decides whether to close.
+    HalfOpen,
+    /// Opened by an operator, stays open until reset.
+    ForcedOpen,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerOptions {
+    /// Consecutive failures opening the circuit, 0 never opens it.
+    pub failure_threshold: u32,
+    pub open_for: Duration,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitState,
+    failures: u32,
+    // When the circuit opened, or the trial call started once half open.
+    opened_at: Instant,
+    probing: bool,
+}
+
+/// Stops calling an upstream that keeps failing, so requests fail fast
+/// instead of piling up on timeouts.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    options: CircuitBreakerOptions,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(options: CircuitBreakerOptions) -> Self {
+        Self {
+            options,
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                failures: 0,
+                opened_at: Instant::now(),
+                probing: false,
+            }),
+        }
+    }
+
+    /// Whether a call may be made now. Once half open, only one trial call
+    /// is let through until its result is recorded, or another cool down
+    /// passed without one.
+    pub fn allow(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::ForcedOpen => false,
+            CircuitState::Open | CircuitState::HalfOpen => {
+                let waiting = match inner.state {
+                    CircuitState::HalfOpen => inner.probing,
+                    _ => true,
+                };
+                if waiting && inner.opened_at.elapsed() < self.options.open_for
+                {
+                    return false;
+                }
+                inner.state = CircuitState::HalfOpen;
+                inner.opened_at = Instant::now();
+                inner.probing = true;
+                true
+            }
+        }
+    }
+
+    pub fn record(&self, success: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.probing = false;
+        match (inner.state, success) {
+            (CircuitState::ForcedOpen, _) => {}
+            (_, true) => {
+                inner.state = CircuitState::Closed;
+                inner.failures = 0;
+            }
+            (CircuitState::HalfOpen, false) => Self::open(&mut inner),
+            (_, false) => {
+                inner.failures += 1;
+                let threshold = self.options.failure_threshold;
+                if threshold > 0 && inner.failures >= threshold {
+                    Self::open(&mut inner);
+                }
+            }
+        }
+    }
+
+    pub fn force_open(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = CircuitState::ForcedOpen;
+        inner.opened_at = Instant::now();
+    }
+
+    pub fn reset(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = CircuitState::Closed;
+        inner.failures = 0;
+        inner.probing = false;
+    }
+
+    /// The state and the number of consecutive failures.
+    pub fn state(&self) -> (CircuitState, u32) {
+        let inner = self.inner.lock().unwrap();
+        (inner.state, inner.failures)
+    }
+
+    fn open(inner: &mut Inner) {
+        inner.state = CircuitState::Open;
+        inner.opened_at = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lets_a_single_trial_call_through_when_half_open() {
+        let breaker = CircuitBreaker::new(CircuitBreakerOptions {
+            failure_threshold: 1,
+            open_for: Duration::from_millis(50),
+        });
+        breaker.record(false);
+        assert!(!breaker.allow());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        breaker.record(true);
+        assert!(breaker.allow());
+        assert!(breaker.allow());
+        // A trial call that never reports is given up after a cool down.
+        breaker.record(false);
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures_and_recovers() {
+        let breaker = CircuitBreaker::new(CircuitBreakerOptions {
+            failure_threshold: 2,
+            open_for: Duration::ZERO,
+        });
+        breaker.record(false);
+        breaker.record(true);
+        breaker.record(false);
+        assert_eq!(breaker.state(), (CircuitState::Closed, 1));
+        breaker.record(false);
+        assert_eq!(breaker.state().0, CircuitState::Open);
+        // The cool down is over at once, one trial call is let through.
+        assert!(breaker.allow());
+        assert_eq!(breaker.state().0, CircuitState::HalfOpen);
+        breaker.record(false);
+        assert_eq!(breaker.state().0, CircuitState::Open);
+        assert!(breaker.allow());
+        breaker.record(true);
+        assert_eq!(breaker.state(), (CircuitState::Closed, 0));
+
+        breaker.force_open();
+        breaker.record(true);
+        assert!(!breaker.allow());
+        breaker.reset();
+        assert!(breaker.allow());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::utils::metrics::Metrics;
+use crate::utils::upstream::{Upstream, Upstreams};
+use actix_web::rt::{net::TcpStream, time::timeout};
+use futures::future::join_all;
+use futures::lock::Mutex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessStatus {
+    Ready,
+    NotReady,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamHealth {
+    pub status: UpstreamStatus,
+    pub critical: bool,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub status: ReadinessStatus,
+    pub checked_at: String,
+    pub upstreams: BTreeMap<&'static str, UpstreamHealth>,
+}
+
+pub struct HealthCheckOptions {
+    /// Names of the upstreams the gateway is not ready without.
+    pub critical: Vec<String>,
+    pub cache_ttl: Duration,
+    pub probe_timeout: Duration,
+}
+
+/// Checks that upstreams accept connections, results are cached for a while
+/// so that frequent probes of several instances do not hammer upstreams.
+pub struct HealthChecker {
+    upstreams: Vec<(Upstream, bool)>,
+    options: HealthCheckOptions,
+    metrics: Metrics,
+    // Held while checking, so concurrent probes wait for one check.
+    last: Mutex<Option<(Instant, Readiness)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub age_ms: Option<u128>,
+    pub ttl_ms: u128,
+}
+
+impl HealthChecker {
+    pub fn new(
+        upstreams: &Upstreams,
+        options: HealthCheckOptions,
+        metrics: Metrics,
+    ) -> Result<Self, String> {
+        let all = upstreams.all();
+        if let Some(unknown) = options
+            .critical
+            .iter()
+            .find(|name| !all.iter().any(|u| u.name == name.as_str()))
+        {
+            return Err(format!("unknown upstream {}", unknown));
+        }
+        Ok(Self {
+            upstreams: all
+                .into_iter()
+                .map(|u| {
+                    let critical = options.critical.iter().any(|c| c == u.name);
+                    (u.clone(), critical)
+                })
+                .collect(),
+            options,
+            metrics,
+            last: Mutex::new(None),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    pub async fn readiness(&self) -> Readiness {
+        let mut last = self.last.lock().await;
+        if let Some((checked, readiness)) = last.as_ref() {
+            if checked.elapsed() < self.options.cache_ttl {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return readiness.clone();
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let readiness = self.check().await;
+        *last = Some((Instant::now(), readiness.clone()));
+        readiness
+    }
+
+    /// The last readiness report, without checking again.
+    pub async fn cached(&self) -> Option<Readiness> {
+        let last = self.last.lock().await;
+        last.as_ref().map(|(_, readiness)| readiness.clone())
+    }
+
+    pub async fn cache_stats(&self) -> CacheStats {
+        let last = self.last.lock().await;
+        CacheStats {
+            entries: usize::from(last.is_some()),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            age_ms: last
+                .as_ref()
+                .map(|(checked, _)| checked.elapsed().as_millis()),
+            ttl_ms: self.options.cache_ttl.as_millis(),
+        }
+    }
+
+    /// Drops the cached report, the next probe checks again.
+    pub async fn flush(&self) {
+        *self.last.lock().await = None;
+    }
+
+    async fn check(&self) -> Readiness {
+        let results = join_all(
+            self.upstreams
+                .iter()
+                .map(|(upstream, _)| self.probe(upstream)),
+        )
+        .await;
+        let mut status = ReadinessStatus::Ready;
+        let mut upstreams = BTreeMap::new();
+        for ((upstream, critical), (latency, error)) in
+            self.upstreams.iter().zip(results)
+        {
+            let up = error.is_none();
+            self.metrics.upstream_health(upstream.name, up);
+            if !up && *critical {
+                status = ReadinessStatus::NotReady;
+            }
+            upstreams.insert(
+                upstream.name,
+                UpstreamHealth {
+                    status: if up {
+                        UpstreamStatus::Up
+                    } else {
+                        UpstreamStatus::Down
+                    },
+                    critical: *critical,
+                    latency_ms: latency.as_secs_f64() * 1000.0,
+                    error,
+                },
+            );
+        }
+        Readiness {
+            status,
+            checked_at: chrono::Utc::now().to_rfc3339(),
+            upstreams,
+        }
+    }
+
+    /// Connects to `upstream`, returns how long it took and why it failed.
+    async fn probe(&self, upstream: &Upstream) -> (Duration, Option<String>) {
+        let started = Instant::now();
+        let connected = timeout(
+            self.options.probe_timeout,
+            TcpStream::connect(upstream.address.as_str()),
+        )
+        .await;
+        let error = match connected {
+            Ok(Ok(_)) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(_) => Some("timed out".to_owned()),
+        };
+        (started.elapsed(), error)
+    }
+}
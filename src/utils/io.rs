@@ -1,6 +1,11 @@
-use crate::core::error::Error;
+use crate::core::error::{Error, ErrorKind};
+use crate::core::service::ByteStream;
+use actix_web::web::Payload;
 use bytes::{BufMut, Bytes, BytesMut};
-use futures::{Stream, TryStreamExt};
+use futures::channel::mpsc::channel;
+use futures::{SinkExt, Stream, StreamExt, TryStreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub async fn stream_to_bytes(
     stream: impl Stream<Item = Result<Bytes, Error>>,
@@ -15,3 +20,42 @@ pub async fn stream_to_bytes(
         });
     Ok(bs.freeze())
 }
+
+/// Forwards an actix payload through a bounded channel so it can be handed
+/// to clients which require a `Send` stream, without buffering the body.
+pub fn payload_to_stream(mut payload: Payload) -> ByteStream {
+    let (mut tx, rx) = channel(4);
+    actix_web::rt::spawn(async move {
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(|e| Error::new(ErrorKind::Validation, e));
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    Box::pin(rx)
+}
+
+/// Fails the stream with `413 Payload Too Large` as soon as more than `limit`
+/// bytes have passed through, `exceeded` is set so that callers can tell
+/// the cause apart from other transport errors.
+pub fn limit_stream(
+    stream: ByteStream,
+    limit: usize,
+    exceeded: Arc<AtomicBool>,
+) -> ByteStream {
+    let mut total = 0;
+    Box::pin(stream.map(move |chunk| {
+        let chunk = chunk?;
+        total += chunk.len();
+        if total > limit {
+            exceeded.store(true, Ordering::SeqCst);
+            return Err(Error::new(
+                ErrorKind::PayloadTooLarge,
+                format!("payload exceeds the limit of {} bytes", limit),
+            ));
+        }
+        Ok(chunk)
+    }))
+}
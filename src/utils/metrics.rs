@@ -0,0 +1,204 @@
+use crate::core::error::{Error, ErrorKind};
+use prometheus::{
+    histogram_opts, opts, Encoder, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, Registry, TextEncoder,
+};
+use std::time::Duration;
+
+/// The metrics of one gateway, kept in a registry of its own rather than the
+/// process wide default one.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    http_requests_in_flight: IntGauge,
+    upstream_requests: IntCounterVec,
+    upstream_request_duration: HistogramVec,
+    auth_verifications: IntCounterVec,
+    upstream_up: IntGaugeVec,
+    upstream_circuit_open: IntGaugeVec,
+    rate_limit_store_errors: IntCounter,
+}
+
+/// How a call to an upstream ended, the label of upstream metrics.
+fn upstream_outcome(error: Option<&Error>) -> &'static str {
+    match error.map(|e| e.kind) {
+        None => "ok",
+        Some(ErrorKind::UpstreamTimeout) => "timeout",
+        Some(ErrorKind::UpstreamUnavailable) => "unavailable",
+        Some(ErrorKind::UpstreamError) => "server_error",
+        Some(ErrorKind::Internal) => "internal_error",
+        Some(_) => "client_error",
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            opts!("http_requests_total", "Inbound requests served."),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            histogram_opts!(
+                "http_request_duration_seconds",
+                "Time to serve inbound requests, response bodies excluded."
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_requests_in_flight = IntGauge::new(
+            "http_requests_in_flight",
+            "Inbound requests being served.",
+        )
+        .unwrap();
+        let upstream_requests = IntCounterVec::new(
+            opts!("upstream_requests_total", "Requests sent to upstreams."),
+            &["upstream", "outcome"],
+        )
+        .unwrap();
+        let upstream_request_duration = HistogramVec::new(
+            histogram_opts!(
+                "upstream_request_duration_seconds",
+                "Time until upstreams answered, response bodies excluded."
+            ),
+            &["upstream", "outcome"],
+        )
+        .unwrap();
+        let auth_verifications = IntCounterVec::new(
+            opts!(
+                "auth_verifications_total",
+                "Auth token verifications by outcome, cached ones were not \
+                 sent to auth and error means the auth upstream failed."
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let upstream_up = IntGaugeVec::new(
+            opts!(
+                "upstream_up",
+                "Whether the upstream accepted connections when last checked."
+            ),
+            &["upstream"],
+        )
+        .unwrap();
+        let upstream_circuit_open = IntGaugeVec::new(
+            opts!(
+                "upstream_circuit_open",
+                "Whether calls to the upstream fail fast."
+            ),
+            &["upstream"],
+        )
+        .unwrap();
+        let rate_limit_store_errors = IntCounter::new(
+            "rate_limit_store_errors_total",
+            "Rate limit checks let through since the state backend failed.",
+        )
+        .unwrap();
+        for collector in [
+            Box::new(http_requests.clone())
+                as Box<dyn prometheus::core::Collector>,
+            Box::new(http_request_duration.clone()),
+            Box::new(http_requests_in_flight.clone()),
+            Box::new(upstream_requests.clone()),
+            Box::new(upstream_request_duration.clone()),
+            Box::new(auth_verifications.clone()),
+            Box::new(upstream_up.clone()),
+            Box::new(upstream_circuit_open.clone()),
+            Box::new(rate_limit_store_errors.clone()),
+        ] {
+            registry.register(collector).unwrap();
+        }
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            http_requests_in_flight,
+            upstream_requests,
+            upstream_request_duration,
+            auth_verifications,
+            upstream_up,
+            upstream_circuit_open,
+            rate_limit_store_errors,
+        }
+    }
+
+    pub fn request_started(&self) {
+        self.http_requests_in_flight.inc();
+    }
+
+    pub fn request_finished(
+        &self,
+        method: &str,
+        route: &str,
+        status: u16,
+        elapsed: Duration,
+    ) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests_in_flight.dec();
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Records a call to `upstream`, `error` is how it failed if it did.
+    pub fn upstream_called(
+        &self,
+        upstream: &str,
+        error: Option<&Error>,
+        elapsed: Duration,
+    ) {
+        let labels = [upstream, upstream_outcome(error)];
+        self.upstream_requests.with_label_values(&labels).inc();
+        self.upstream_request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts a verification by `outcome`, one of valid, cached, invalid,
+    /// missing or error.
+    pub fn auth_verified(&self, outcome: &str) {
+        self.auth_verifications.with_label_values(&[outcome]).inc();
+    }
+
+    pub fn upstream_health(&self, upstream: &str, up: bool) {
+        self.upstream_up
+            .with_label_values(&[upstream])
+            .set(i64::from(up));
+    }
+
+    pub fn circuit_state(&self, upstream: &str, open: bool) {
+        self.upstream_circuit_open
+            .with_label_values(&[upstream])
+            .set(i64::from(open));
+    }
+
+    pub fn rate_limit_store_failed(&self) {
+        self.rate_limit_store_errors.inc();
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
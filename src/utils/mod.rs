@@ -1,2 +1,11 @@
+pub(crate) mod access_log;
+pub(crate) mod circuit_breaker;
+pub(crate) mod health;
 pub(crate) mod io;
+pub(crate) mod metrics;
+pub(crate) mod network;
+pub(crate) mod redact;
+pub(crate) mod request_id;
 pub(crate) mod restful;
+pub(crate) mod telemetry;
+pub(crate) mod upstream;
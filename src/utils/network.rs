@@ -0,0 +1,290 @@
+use actix_web::{web::Data, HttpRequest};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::core::error::{Error, ErrorKind};
+use crate::state::{StateBackend, StateStore};
+
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// Parses a network in CIDR notation, a bare address is a network of its
+/// own.
+pub fn parse_network(network: &str) -> Result<IpNet, String> {
+    let network = network.trim();
+    network
+        .parse()
+        .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("invalid network {}", network))
+}
+
+/// Networks of the proxies in front of the gateway, only they are believed
+/// about who the client is.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+    /// Parses comma separated networks.
+    pub fn parse(networks: &str) -> Result<Self, String> {
+        networks
+            .split(',')
+            .filter(|network| !network.trim().is_empty())
+            .map(parse_network)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(&ip))
+    }
+
+    /// The peer address, or if the peer is a trusted proxy the last address
+    /// in `X-Forwarded-For` that is not one, clients can prepend anything.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr()?.ip();
+        if !self.trusts(peer) {
+            return Some(peer);
+        }
+        let hops: Vec<&str> = req
+            .headers()
+            .get_all(FORWARDED_FOR_HEADER)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .collect();
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            let Ok(ip) = hop.parse() else {
+                break;
+            };
+            client = ip;
+            if !self.trusts(ip) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/// The client IP of `req` according to the trusted proxies of the app.
+pub(crate) fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    match req.app_data::<Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client_ip(req),
+        None => req.peer_addr().map(|addr| addr.ip()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpBlock {
+    pub network: String,
+    /// None if the block lasts until removed.
+    pub expires_in_secs: Option<u64>,
+}
+
+/// A block as kept in the state backend, expiring at a wall clock time in
+/// milliseconds since the epoch so that every replica agrees.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredIpBlock {
+    network: String,
+    expires_at: Option<u64>,
+}
+
+const IP_BLOCKS_KEY: &str = "ip_blocks";
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
+/// Networks blocked at runtime through the admin API, kept in the state
+/// backend so that every replica applies them. They are stored together,
+/// concurrent changes may overwrite each other.
+#[derive(Clone)]
+pub struct IpBlocklist {
+    state: StateBackend,
+}
+
+impl IpBlocklist {
+    pub fn new(state: StateBackend) -> Self {
+        Self { state }
+    }
+
+    /// The blocks that have not expired.
+    async fn load(&self) -> Result<Vec<(IpNet, Option<u64>)>, Error> {
+        let Some(stored) = self.state.get(IP_BLOCKS_KEY).await? else {
+            return Ok(Vec::new());
+        };
+        let stored: Vec<StoredIpBlock> = serde_json::from_str(&stored)
+            .map_err(Error::wrap(ErrorKind::Internal))?;
+        let now = unix_millis();
+        Ok(stored
+            .into_iter()
+            .filter(|block| block.expires_at.is_none_or(|at| at > now))
+            .filter_map(|block| {
+                Some((parse_network(&block.network).ok()?, block.expires_at))
+            })
+            .collect())
+    }
+
+    /// Stores `blocks` until the last of them expires.
+    async fn store(
+        &self,
+        blocks: Vec<(IpNet, Option<u64>)>,
+    ) -> Result<(), Error> {
+        if blocks.is_empty() {
+            return self.state.remove(IP_BLOCKS_KEY).await;
+        }
+        let now = unix_millis();
+        let ttl = blocks
+            .iter()
+            .try_fold(0, |last, (_, expires_at)| {
+                expires_at.map(|at| last.max(at))
+            })
+            .map(|last| Duration::from_millis(last.saturating_sub(now)));
+        let stored: Vec<StoredIpBlock> = blocks
+            .into_iter()
+            .map(|(network, expires_at)| StoredIpBlock {
+                network: network.to_string(),
+                expires_at,
+            })
+            .collect();
+        let stored = serde_json::to_string(&stored)
+            .map_err(Error::wrap(ErrorKind::Internal))?;
+        self.state.set(IP_BLOCKS_KEY, &stored, ttl).await
+    }
+
+    pub async fn block(
+        &self,
+        network: IpNet,
+        ttl: Option<Duration>,
+    ) -> Result<(), Error> {
+        let network = network.trunc();
+        let expires_at = ttl.map(|ttl| {
+            unix_millis()
+                .saturating_add(ttl.as_millis().try_into().unwrap_or(u64::MAX))
+        });
+        let mut blocks = self.load().await?;
+        blocks.retain(|(blocked, _)| *blocked != network);
+        blocks.push((network, expires_at));
+        self.store(blocks).await
+    }
+
+    /// Returns whether the network was blocked.
+    pub async fn unblock(&self, network: IpNet) -> Result<bool, Error> {
+        let network = network.trunc();
+        let mut blocks = self.load().await?;
+        let count = blocks.len();
+        blocks.retain(|(blocked, _)| *blocked != network);
+        if blocks.len() == count {
+            return Ok(false);
+        }
+        self.store(blocks).await?;
+        Ok(true)
+    }
+
+    pub async fn is_blocked(&self, ip: IpAddr) -> Result<bool, Error> {
+        Ok(self
+            .load()
+            .await?
+            .iter()
+            .any(|(network, _)| network.contains(&ip)))
+    }
+
+    pub async fn list(&self) -> Result<Vec<IpBlock>, Error> {
+        let now = unix_millis();
+        let mut blocks: Vec<IpBlock> = self
+            .load()
+            .await?
+            .into_iter()
+            .map(|(network, expires_at)| IpBlock {
+                network: network.to_string(),
+                expires_in_secs: expires_at
+                    .map(|at| at.saturating_sub(now) / 1000),
+            })
+            .collect();
+        blocks.sort_by(|a, b| a.network.cmp(&b.network));
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn forwarded_for_is_believed_from_trusted_proxies_only() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8, 192.168.0.1").unwrap();
+        for (peer, forwarded_for, expected) in [
+            ("203.0.113.1", None, "203.0.113.1"),
+            ("203.0.113.1", Some("198.51.100.1"), "203.0.113.1"),
+            ("10.0.0.1", Some("198.51.100.1"), "198.51.100.1"),
+            (
+                "10.0.0.1",
+                Some("1.1.1.1, 198.51.100.1, 10.0.0.2"),
+                "198.51.100.1",
+            ),
+            ("192.168.0.1", Some("10.0.0.3, 10.0.0.2"), "10.0.0.3"),
+            ("10.0.0.1", Some("junk, 10.0.0.2"), "10.0.0.2"),
+            ("10.0.0.1", None, "10.0.0.1"),
+        ] {
+            let mut req = TestRequest::default()
+                .peer_addr(format!("{}:4321", peer).parse().unwrap());
+            if let Some(forwarded_for) = forwarded_for {
+                req = req.insert_header((FORWARDED_FOR_HEADER, forwarded_for));
+            }
+            assert_eq!(
+                proxies.client_ip(&req.to_http_request()),
+                Some(expected.parse().unwrap()),
+                "{} {:?}",
+                peer,
+                forwarded_for
+            );
+        }
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+    }
+
+    #[actix_web::test]
+    async fn blocks_are_shared_through_the_state_backend() {
+        let state = StateBackend::memory();
+        let (admin, replica) =
+            (IpBlocklist::new(state.clone()), IpBlocklist::new(state));
+        let ip = "198.51.100.9".parse().unwrap();
+        assert!(!replica.is_blocked(ip).await.unwrap());
+        admin
+            .block(parse_network("198.51.100.1/24").unwrap(), None)
+            .await
+            .unwrap();
+        admin
+            .block(
+                parse_network("203.0.113.7").unwrap(),
+                Some(Duration::from_millis(50)),
+            )
+            .await
+            .unwrap();
+        assert!(replica.is_blocked(ip).await.unwrap());
+        let networks = |blocks: Vec<IpBlock>| {
+            blocks
+                .into_iter()
+                .map(|block| block.network)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            networks(replica.list().await.unwrap()),
+            ["198.51.100.0/24", "203.0.113.7/32"]
+        );
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            networks(replica.list().await.unwrap()),
+            ["198.51.100.0/24"]
+        );
+        assert!(admin
+            .unblock(parse_network("198.51.100.0/24").unwrap())
+            .await
+            .unwrap());
+        assert!(!replica.is_blocked(ip).await.unwrap());
+        assert!(replica.list().await.unwrap().is_empty());
+    }
+}
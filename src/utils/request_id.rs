@@ -0,0 +1,39 @@
+use std::future::Future;
+
+/// The header carrying the id which ties together the logs of the gateway
+/// and of the upstreams for one request.
+pub const REQUEST_ID_HEADER: &str = "X-Request-ID";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being served by the current task, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Calls `f` and polls the future it returns with `id` as the current
+/// request id.
+pub fn scope<F, Fut>(id: String, f: F) -> impl Future<Output = Fut::Output>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future,
+{
+    let fut = REQUEST_ID.sync_scope(id.clone(), f);
+    REQUEST_ID.scope(id, fut)
+}
+
+/// Accepts ids of reasonable length made of characters safe to log and
+/// forward, anything else is replaced by a generated id.
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+pub fn generate() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
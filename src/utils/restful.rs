@@ -1,17 +1,24 @@
-use crate::core::error::Error;
+use crate::core::error::{Error, ErrorKind};
 use crate::core::service::ByteStream;
+use crate::utils::access_log;
+use crate::utils::request_id::{self, REQUEST_ID_HEADER};
+use crate::utils::telemetry;
+use crate::utils::upstream::Upstream;
 use actix_web::{FromRequest, HttpRequest};
 use futures::TryStreamExt;
-use http::StatusCode;
 use nb_serde_query::from_str;
 use nb_serde_query::to_string as to_query;
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt};
+use opentelemetry::KeyValue;
 use reqwest::{
-    header::HeaderMap, multipart::Form, Client, Method, RequestBuilder,
+    header::{HeaderMap, HeaderValue},
+    multipart::Form,
+    Client, Method, RequestBuilder, Response,
 };
 use serde::Deserialize;
 use serde::Serialize;
-use std::time::Duration;
-use url::Url;
+use std::time::{Duration, Instant};
+use url::{form_urlencoded, Url};
 
 pub enum RequestBody<J>
 where
@@ -22,27 +29,26 @@ where
     MultipartForm(Form),
 }
 
-pub(crate) async fn make_request<H, P, Q, J>(
+pub(crate) async fn make_request<P, Q, J>(
     method: Method,
-    host_and_port: H,
+    upstream: &Upstream,
     path: P,
     headers: Option<HeaderMap>,
     params: Option<Q>,
     body: RequestBody<J>,
 ) -> Result<ByteStream, Error>
 where
-    H: Into<String>,
     P: Into<String>,
     Q: Serialize,
     J: Serialize,
 {
-    let mut url = Url::parse(&format!("http://{}", host_and_port.into()))
-        .map_err(Error::wrap(StatusCode::BAD_REQUEST.as_u16()))?
+    let mut url = Url::parse(&format!("http://{}", upstream.address))
+        .map_err(Error::wrap(ErrorKind::Internal))?
         .join(&path.into())
-        .map_err(Error::wrap(StatusCode::BAD_REQUEST.as_u16()))?;
+        .map_err(Error::wrap(ErrorKind::Internal))?;
     let params = params
         .map_or(Ok(None), |q| to_query(q).map(Some))
-        .map_err(Error::wrap(StatusCode::BAD_REQUEST.as_u16()))?;
+        .map_err(|e| Error::new(ErrorKind::Internal, e))?;
     url.set_query(params.as_deref());
     let mut builder = Client::new()
         .request(method, url)
@@ -50,13 +56,10 @@ where
     if let Some(headers) = headers {
         builder = builder.headers(headers);
     }
-    if let Some(params) = params {
-        builder = builder.query(&params);
-    }
     match body {
         RequestBody::Json(body) => {
             let json = serde_json::to_string(&body)
-                .map_err(Error::wrap(StatusCode::BAD_REQUEST.as_u16()))?;
+                .map_err(Error::wrap(ErrorKind::Internal))?;
             builder = builder
                 .header("Content-Type", "application/json")
                 .body(json);
@@ -66,36 +69,105 @@ where
         }
         RequestBody::None => {}
     }
-    let resp = builder
-        .send()
+    request(upstream, builder).await
+}
+
+pub async fn send(
+    upstream: &Upstream,
+    builder: RequestBuilder,
+) -> Result<Response, Error> {
+    send_with(upstream, builder, |e| e).await
+}
+
+/// Like `send`, `map_error` turns failures caused by the client, such as a
+/// body stream it broke, into their own errors before they are counted
+/// against the upstream.
+pub async fn send_with(
+    upstream: &Upstream,
+    builder: RequestBuilder,
+    map_error: impl FnOnce(Error) -> Error,
+) -> Result<Response, Error> {
+    let (client, req) = builder.build_split();
+    let mut req = req.map_err(Error::wrap(ErrorKind::Internal))?;
+    upstream.admit()?;
+    if let Some(id) = request_id::current() {
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            req.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+    }
+    let cx = telemetry::start_span(
+        format!("{} {}", req.method(), upstream.name),
+        SpanKind::Client,
+    );
+    cx.span().set_attributes([
+        KeyValue::new("http.request.method", req.method().to_string()),
+        KeyValue::new("url.full", telemetry::redact(req.url().as_str())),
+        KeyValue::new("peer.service", upstream.name),
+    ]);
+    telemetry::inject(&cx, req.headers_mut());
+    let started = Instant::now();
+    let result = execute(upstream, client, req)
+        .with_context(cx.clone())
         .await
-        .map_err(Error::wrap(StatusCode::INTERNAL_SERVER_ERROR.as_u16()))?;
-    if !resp.status().is_success() {
-        let status_code = resp.status();
-        let reason = resp.text().await.map_err(|e| {
-            Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-        })?;
-        return Err(Error::new(status_code.as_u16(), reason));
+        .map_err(map_error);
+    let elapsed = started.elapsed();
+    upstream.metrics.upstream_called(
+        upstream.name,
+        result.as_ref().err(),
+        elapsed,
+    );
+    access_log::record_upstream_call(upstream.name, elapsed);
+    upstream.record(result.as_ref().err());
+    match &result {
+        Ok(resp) => cx.span().set_attribute(KeyValue::new(
+            "http.response.status_code",
+            i64::from(resp.status().as_u16()),
+        )),
+        Err(e) => cx
+            .span()
+            .set_status(Status::error(telemetry::redact(&e.to_string()))),
     }
-    Ok(Box::pin(resp.bytes_stream().map_err(|e| {
-        Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-    })))
+    cx.span().end();
+    result
 }
 
-pub async fn request(builder: RequestBuilder) -> Result<ByteStream, Error> {
-    let resp = builder.send().await.map_err(|e| {
-        Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-    })?;
+async fn execute(
+    upstream: &Upstream,
+    client: Client,
+    req: reqwest::Request,
+) -> Result<Response, Error> {
+    let resp = client
+        .execute(req)
+        .await
+        .map_err(|e| Error::upstream_unreachable(upstream.name, e))?;
     if !resp.status().is_success() {
         let status_code = resp.status();
-        let reason = resp.text().await.map_err(|e| {
-            Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-        })?;
-        return Err(Error::new(status_code.as_u16(), reason));
+        let reason = resp
+            .text()
+            .await
+            .map_err(|e| Error::upstream_unreachable(upstream.name, e))?;
+        return Err(Error::from_upstream(
+            upstream.name,
+            status_code.as_u16(),
+            &reason,
+        ));
     }
-    Ok(Box::pin(resp.bytes_stream().map_err(|e| {
-        Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-    })))
+    Ok(resp)
+}
+
+pub fn response_to_stream(upstream: &Upstream, resp: Response) -> ByteStream {
+    let name = upstream.name;
+    Box::pin(
+        resp.bytes_stream()
+            .map_err(move |e| Error::upstream_unreachable(name, e)),
+    )
+}
+
+pub async fn request(
+    upstream: &Upstream,
+    builder: RequestBuilder,
+) -> Result<ByteStream, Error> {
+    Ok(response_to_stream(upstream, send(upstream, builder).await?))
 }
 
 // pub fn extract_user_id(req: &HttpRequest) -> Result<&str, Error> {
@@ -124,15 +196,29 @@ pub fn parse_url(
     path: &str,
     params: Option<&str>,
 ) -> Result<Url, Error> {
-    let mut url =
-        Url::parse(&format!("http://{}", host_and_port)).map_err(|e| {
-            Error::new(StatusCode::INTERNAL_SERVER_ERROR.as_u16(), e)
-        })?;
+    let mut url = Url::parse(&format!("http://{}", host_and_port))
+        .map_err(Error::wrap(ErrorKind::Internal))?;
     url.set_path(path);
     url.set_query(params);
     Ok(url)
 }
 
+/// Decodes a raw query string: the percent escapes are resolved before
+/// `nb_serde_query` splits it, values with `&` or `=` are thus refused.
+pub fn from_query<T>(query: &str) -> Result<T, Error>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let mut pairs = Vec::new();
+    for (k, v) in form_urlencoded::parse(query.as_bytes()) {
+        if [&k, &v].iter().any(|s| s.contains(['&', '='])) {
+            return Err(Error::invalid_query("reserved character in query"));
+        }
+        pairs.push(format!("{}={}", k, v));
+    }
+    from_str(&pairs.join("&")).map_err(Error::invalid_query)
+}
+
 pub struct Query<T>(pub T);
 
 impl<T> FromRequest for Query<T>
@@ -146,10 +232,7 @@ where
         req: &HttpRequest,
         _payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
-        let query = req.query_string();
-        match from_str(query)
-            .map_err(|e| Error::new(StatusCode::BAD_REQUEST.as_u16(), e))
-        {
+        match from_query(req.query_string()) {
             Ok(v) => futures::future::ready(Ok(Query(v))),
             Err(e) => futures::future::ready(Err(e)),
         }
@@ -171,15 +254,14 @@ impl FromRequest for UserID {
                     )))
                 }
                 Err(e) => {
-                    return futures::future::ready(Err(Error::new(
-                        StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-                        e,
-                    )))
+                    return futures::future::ready(Err(Error::wrap(
+                        ErrorKind::Internal,
+                    )(e)))
                 }
             }
         }
         futures::future::ready(Err(Error::new(
-            StatusCode::UNAUTHORIZED.as_u16(),
+            ErrorKind::Authentication,
             "no user id",
         )))
     }
@@ -196,15 +278,14 @@ impl FromRequest for UserID {
                     )))
                 }
                 Err(e) => {
-                    return futures::future::ready(Err(Error::new(
-                        StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-                        e,
-                    )))
+                    return futures::future::ready(Err(Error::wrap(
+                        ErrorKind::Internal,
+                    )(e)))
                 }
             }
         }
         futures::future::ready(Err(Error::new(
-            StatusCode::UNAUTHORIZED.as_u16(),
+            ErrorKind::Authentication,
             "no user id",
         )))
     }
@@ -0,0 +1,152 @@
+use crate::config::Config;
+use crate::utils::redact::Redactor;
+use futures::Future;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{
+    FutureExt, SpanKind, Status, TraceContextExt, Tracer as _,
+    TracerProvider as _,
+};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::sync::Arc;
+
+/// Owns the tracer provider of a gateway, spans are exported over OTLP/HTTP
+/// when an endpoint is configured and dropped otherwise.
+#[derive(Clone)]
+pub struct Telemetry {
+    provider: TracerProvider,
+    tracer: Tracer,
+}
+
+impl Telemetry {
+    pub fn from_config(config: &Config) -> Self {
+        let mut builder = TracerProvider::builder().with_config(
+            trace::config().with_resource(Resource::new([KeyValue::new(
+                "service.name",
+                config.otlp_service_name.clone(),
+            )])),
+        );
+        if !config.otlp_endpoint.is_empty() {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(config.otlp_endpoint.trim_end_matches('/'))
+                .build_span_exporter()
+                .expect("OTLP_ENDPOINT must be a valid URL");
+            builder = builder
+                .with_batch_exporter(exporter, runtime::TokioCurrentThread);
+        }
+        let provider = builder.build();
+        let tracer = provider.tracer("little-walk-api-gateway");
+        Self { provider, tracer }
+    }
+
+    pub fn tracer(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    /// Exports the finished spans now, blocks until the exporter is done.
+    pub fn force_flush(&self) {
+        for result in self.provider.force_flush() {
+            if let Err(e) = result {
+                log::warn!("failed to export spans: {}", e);
+            }
+        }
+    }
+}
+
+/// Made available to nested spans through the context of the inbound span,
+/// so that code serving a request needs no tracer of its own.
+#[derive(Clone)]
+struct ActiveTracer {
+    tracer: Tracer,
+    redactor: Arc<Redactor>,
+}
+
+/// Starts the server span of an inbound request, continuing the trace of the
+/// caller if its headers carry one.
+pub(crate) fn start_request(
+    tracer: &Tracer,
+    redactor: &Arc<Redactor>,
+    headers: &actix_web::http::header::HeaderMap,
+    name: String,
+    attributes: Vec<KeyValue>,
+) -> Context {
+    let parent =
+        TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Server)
+        .with_attributes(attributes)
+        .start_with_context(tracer, &parent);
+    parent.with_span(span).with_value(ActiveTracer {
+        tracer: tracer.clone(),
+        redactor: redactor.clone(),
+    })
+}
+
+/// Starts a child of the current span, outside of a traced request the
+/// current context is returned as is.
+pub(crate) fn start_span<N>(name: N, kind: SpanKind) -> Context
+where
+    N: Into<Cow<'static, str>>,
+{
+    let cx = Context::current();
+    let Some(ActiveTracer { tracer, .. }) = cx.get::<ActiveTracer>() else {
+        return cx;
+    };
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .start_with_context(tracer, &cx);
+    cx.with_span(span)
+}
+
+/// Polls `fut` within a new internal span, which is marked as failed if the
+/// future resolves to an error.
+pub(crate) async fn in_span<N, F, T, E>(name: N, fut: F) -> Result<T, E>
+where
+    N: Into<Cow<'static, str>>,
+    F: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    let cx = start_span(name, SpanKind::Internal);
+    let result = fut.with_context(cx.clone()).await;
+    if let Err(e) = &result {
+        cx.span().set_status(Status::error(redact(&e.to_string())));
+    }
+    cx.span().end();
+    result
+}
+
+/// Masks secrets in `text` before it becomes part of a span, with the rules
+/// of the request being traced.
+pub(crate) fn redact(text: &str) -> String {
+    match Context::current().get::<ActiveTracer>() {
+        Some(active) => active.redactor.redact(text).into_owned(),
+        None => Redactor::builtin().redact(text).into_owned(),
+    }
+}
+
+/// Writes `traceparent` and `tracestate` of `cx` into upstream request
+/// headers.
+pub(crate) fn inject(cx: &Context, headers: &mut reqwest::header::HeaderMap) {
+    TraceContextPropagator::new()
+        .inject_context(cx, &mut opentelemetry_http::HeaderInjector(headers));
+}
+
+struct HeaderExtractor<'a>(&'a actix_web::http::header::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
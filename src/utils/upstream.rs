@@ -0,0 +1,152 @@
+use crate::config::Config;
+use crate::core::error::{Error, ErrorKind};
+use crate::utils::circuit_breaker::{
+    CircuitBreaker, CircuitBreakerOptions, CircuitState,
+};
+use crate::utils::metrics::Metrics;
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A backend service the gateway forwards to, `name` identifies it in error
+/// responses. Clones share the circuit breaker and the drained flag.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub name: &'static str,
+    pub address: String,
+    pub metrics: Metrics,
+    breaker: Arc<CircuitBreaker>,
+    drained: Arc<AtomicBool>,
+}
+
+impl Upstream {
+    pub fn new(
+        name: &'static str,
+        address: &str,
+        metrics: &Metrics,
+        breaker: CircuitBreakerOptions,
+    ) -> Self {
+        Self {
+            name,
+            address: address.to_owned(),
+            metrics: metrics.clone(),
+            breaker: Arc::new(CircuitBreaker::new(breaker)),
+            drained: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Maps a response of this upstream that could not be understood.
+    pub fn invalid_response<E>(&self) -> impl FnOnce(E) -> Error
+    where
+        E: StdError + Send + Sync + 'static,
+    {
+        let name = self.name;
+        move |e| Error::wrap(ErrorKind::UpstreamError)(e).with_upstream(name)
+    }
+
+    /// Fails fast if the upstream is drained or its circuit is open.
+    pub fn admit(&self) -> Result<(), Error> {
+        let reason = if self.is_drained() {
+            "upstream drained"
+        } else if !self.breaker.allow() {
+            "circuit open"
+        } else {
+            return Ok(());
+        };
+        self.update_circuit_metric();
+        Err(Error::new(ErrorKind::UpstreamUnavailable, reason)
+            .with_upstream(self.name))
+    }
+
+    /// Records the result of a call, only failures of the upstream itself
+    /// count against the circuit.
+    pub fn record(&self, error: Option<&Error>) {
+        let failed = error.is_some_and(|e| {
+            matches!(
+                e.kind,
+                ErrorKind::UpstreamUnavailable
+                    | ErrorKind::UpstreamTimeout
+                    | ErrorKind::UpstreamError
+            )
+        });
+        self.breaker.record(!failed);
+        self.update_circuit_metric();
+    }
+
+    pub fn circuit(&self) -> (CircuitState, u32) {
+        self.breaker.state()
+    }
+
+    pub fn force_open_circuit(&self) {
+        self.breaker.force_open();
+        self.update_circuit_metric();
+    }
+
+    pub fn reset_circuit(&self) {
+        self.breaker.reset();
+        self.update_circuit_metric();
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::Relaxed)
+    }
+
+    /// A drained upstream gets no new requests, e.g. during maintenance.
+    pub fn set_drained(&self, drained: bool) {
+        self.drained.store(drained, Ordering::Relaxed);
+    }
+
+    fn update_circuit_metric(&self) {
+        let (state, _) = self.breaker.state();
+        self.metrics
+            .circuit_state(self.name, state != CircuitState::Closed);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Upstreams {
+    pub auth: Upstream,
+    pub upload: Upstream,
+    pub sms_verification_code: Upstream,
+    pub dog: Upstream,
+    pub walk_request: Upstream,
+}
+
+impl Upstreams {
+    pub fn from_config(config: &Config, metrics: &Metrics) -> Self {
+        let breaker = CircuitBreakerOptions {
+            failure_threshold: config.circuit_breaker_failure_threshold,
+            open_for: Duration::from_secs(config.circuit_breaker_open_secs),
+        };
+        let upstream =
+            |name, address| Upstream::new(name, address, metrics, breaker);
+        Self {
+            auth: upstream("auth", &config.auth_service_address),
+            upload: upstream("upload", &config.upload_service_address),
+            sms_verification_code: upstream(
+                "sms_verification_code",
+                &config.sms_verification_code_service_address,
+            ),
+            dog: upstream("dog", &config.dog_service_address),
+            walk_request: upstream(
+                "walk_request",
+                &config.walk_request_service_address,
+            ),
+        }
+    }
+
+    pub fn all(&self) -> Vec<&Upstream> {
+        vec![
+            &self.auth,
+            &self.upload,
+            &self.sms_verification_code,
+            &self.dog,
+            &self.walk_request,
+        ]
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Upstream> {
+        self.all().into_iter().find(|u| u.name == name)
+    }
+}
@@ -0,0 +1,337 @@
+//! Harness running the gateway `App` against local mock upstreams bound to
+//! ephemeral ports.
+#![allow(dead_code)]
+
+use actix_web::{
+    dev::ServerHandle,
+    http::{Method, StatusCode},
+    web::{self, Bytes},
+    App, HttpRequest, HttpResponse, HttpServer,
+};
+use little_walk_api_gateway::Config;
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, Once};
+
+/// A request as received by a mock upstream.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: String,
+    pub headers: HashMap<String, String>,
+    pub body: Bytes,
+}
+
+impl ReceivedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+}
+
+#[derive(Clone)]
+struct MockResponse {
+    status: StatusCode,
+    content_type: String,
+    body: Bytes,
+}
+
+#[derive(Default)]
+struct MockState {
+    received: Vec<ReceivedRequest>,
+    responses: HashMap<(Method, String), MockResponse>,
+}
+
+/// An HTTP server recording every request and answering with the scripted
+/// response for its method and path, or `200 {}` if there is none.
+pub struct MockUpstream {
+    address: String,
+    state: Arc<Mutex<MockState>>,
+    handle: ServerHandle,
+}
+
+async fn record(
+    state: web::Data<Mutex<MockState>>,
+    req: HttpRequest,
+    body: Bytes,
+) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    state.received.push(ReceivedRequest {
+        method: req.method().clone(),
+        path: req.path().to_owned(),
+        query: req.query_string().to_owned(),
+        headers: req
+            .headers()
+            .iter()
+            .map(|(k, v)| {
+                (k.as_str().to_owned(), v.to_str().unwrap_or("").to_owned())
+            })
+            .collect(),
+        body,
+    });
+    match state
+        .responses
+        .get(&(req.method().clone(), req.path().to_owned()))
+    {
+        Some(resp) => HttpResponse::build(resp.status)
+            .content_type(resp.content_type.as_str())
+            .body(resp.body.clone()),
+        None => HttpResponse::Ok()
+            .content_type("application/json")
+            .body("{}"),
+    }
+}
+
+impl MockUpstream {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let data = web::Data::from(state.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .default_service(web::route().to(record))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        Self {
+            address,
+            state,
+            handle,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Answers `method path` with `status` and a JSON `body`.
+    pub fn respond(&self, method: Method, path: &str, status: u16, body: &str) {
+        self.respond_with(method, path, status, "application/json", body);
+    }
+
+    pub fn respond_with(
+        &self,
+        method: Method,
+        path: &str,
+        status: u16,
+        content_type: &str,
+        body: &str,
+    ) {
+        self.state.lock().unwrap().responses.insert(
+            (method, path.to_owned()),
+            MockResponse {
+                status: StatusCode::from_u16(status).unwrap(),
+                content_type: content_type.to_owned(),
+                body: Bytes::copy_from_slice(body.as_bytes()),
+            },
+        );
+    }
+
+    pub fn received(&self) -> Vec<ReceivedRequest> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    pub fn received_on(&self, path: &str) -> Vec<ReceivedRequest> {
+        self.received()
+            .into_iter()
+            .filter(|r| r.path == path)
+            .collect()
+    }
+
+    /// Returns the only request received on `path`, panics otherwise.
+    pub fn single(&self, path: &str) -> ReceivedRequest {
+        let mut received = self.received_on(path);
+        assert_eq!(received.len(), 1, "requests on {}: {:?}", path, received);
+        received.remove(0)
+    }
+}
+
+impl Drop for MockUpstream {
+    fn drop(&mut self) {
+        let handle = self.handle.clone();
+        actix_web::rt::spawn(async move { handle.stop(false).await });
+    }
+}
+
+/// One mock for every upstream service of the gateway.
+pub struct Upstreams {
+    pub auth: MockUpstream,
+    pub upload: MockUpstream,
+    pub sms_verification_code: MockUpstream,
+    pub dog: MockUpstream,
+    pub walk_request: MockUpstream,
+}
+
+impl Upstreams {
+    pub fn start() -> Self {
+        Self {
+            auth: MockUpstream::start(),
+            upload: MockUpstream::start(),
+            sms_verification_code: MockUpstream::start(),
+            dog: MockUpstream::start(),
+            walk_request: MockUpstream::start(),
+        }
+    }
+
+    /// Makes the auth upstream accept `token` as the token of `user_id`.
+    pub fn accept_token(&self, token: &str, user_id: &str) {
+        self.auth.respond(
+            Method::GET,
+            &format!("/tokens/{}/verification", token),
+            200,
+            &format!(r#"{{"id":"{}"}}"#, user_id),
+        );
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            listen_address: "127.0.0.1:0".to_owned(),
+            admin_listen_address: "127.0.0.1:0".to_owned(),
+            log_level: "info".to_owned(),
+            log_format: "%t %{X-Request-ID}i %s %r %a %D".to_owned(),
+            log_json: false,
+            redact_rules: "tokens,phones,codes,passwords".to_owned(),
+            redact_patterns: "".to_owned(),
+            auth_service_address: self.auth.address().to_owned(),
+            upload_service_address: self.upload.address().to_owned(),
+            sms_verification_code_service_address: self
+                .sms_verification_code
+                .address()
+                .to_owned(),
+            dog_service_address: self.dog.address().to_owned(),
+            walk_request_service_address: self
+                .walk_request
+                .address()
+                .to_owned(),
+            upload_size_limit: 1024,
+            sms_login_max_attempts: 5,
+            sms_login_attempt_window_secs: 600,
+            sms_code_ttl_secs: 600,
+            sms_login_auto_signup: false,
+            sms_phone_pattern: "^1[3-9][0-9]{9}$".to_owned(),
+            sms_send_cooldown_secs: 60,
+            sms_send_daily_limit_per_phone: 10,
+            sms_send_daily_limit_per_ip: 50,
+            sms_test_phones: "".to_owned(),
+            sms_test_code: "123456".to_owned(),
+            login_max_failures_per_phone: 5,
+            login_max_failures_per_ip: 20,
+            login_failure_window_secs: 900,
+            login_lockout_secs: 900,
+            login_delay_after_failures: 2,
+            login_delay_ms: 0,
+            login_max_delay_ms: 0,
+            nearby_default_radius: 3000.0,
+            sanitize_upstream_errors: true,
+            default_locale: "en".to_owned(),
+            otlp_endpoint: "".to_owned(),
+            otlp_service_name: "little-walk-api-gateway".to_owned(),
+            circuit_breaker_failure_threshold: 5,
+            circuit_breaker_open_secs: 30,
+            readiness_critical_upstreams:
+                "auth,upload,sms_verification_code,dog,walk_request".to_owned(),
+            readiness_cache_ttl_ms: 5000,
+            readiness_probe_timeout_ms: 1000,
+            rate_limits: "".to_owned(),
+            rate_limit_api_keys: "".to_owned(),
+            state_backend_url: "memory".to_owned(),
+            state_key_prefix: "little-walk-api-gateway:".to_owned(),
+            state_backend_timeout_ms: 500,
+            auth_cache_ttl_secs: 0,
+            idempotency_ttl_secs: 86400,
+            cors_policies: "[]".to_owned(),
+            strict_transport_security: "max-age=31536000; includeSubDomains"
+                .to_owned(),
+            x_content_type_options: "nosniff".to_owned(),
+            referrer_policy: "no-referrer".to_owned(),
+            content_security_policy:
+                "default-src 'self'; frame-ancestors 'none'".to_owned(),
+            max_url_length: 4096,
+            max_header_count: 64,
+            max_header_bytes: 16384,
+            body_size_limits: "*=65536".to_owned(),
+            trusted_proxies: "".to_owned(),
+            ip_rules: "[]".to_owned(),
+            admin_ip_rules: "[]".to_owned(),
+        }
+    }
+}
+
+/// Calls the app the way the server does, turning errors into responses.
+pub async fn call<S, B>(
+    app: &S,
+    req: actix_http::Request,
+) -> (StatusCode, Bytes)
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse<B>,
+        Error = actix_web::Error,
+    >,
+    B: actix_web::body::MessageBody,
+{
+    match app.call(req).await {
+        Ok(res) => {
+            let status = res.status();
+            (status, actix_web::test::read_body(res).await)
+        }
+        Err(e) => {
+            let res = e.error_response();
+            let status = res.status();
+            let body = actix_web::body::to_bytes(res.into_body())
+                .await
+                .unwrap_or_default();
+            (status, body)
+        }
+    }
+}
+
+static ACCESS_LOGS: Mutex<Vec<serde_json::Value>> = Mutex::new(Vec::new());
+
+struct AccessLogCapture;
+
+impl log::Log for AccessLogCapture {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target() == little_walk_api_gateway::ACCESS_LOG_TARGET
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let line = record.args().to_string();
+            ACCESS_LOGS
+                .lock()
+                .unwrap()
+                .push(serde_json::from_str(&line).unwrap());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Captures the JSON access log lines written from now on, tests run in
+/// parallel so lines are looked up by request id.
+pub fn capture_access_logs() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_boxed_logger(Box::new(AccessLogCapture)).unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+    });
+}
+
+/// The access log lines of the request with `request_id`.
+pub fn access_logs(request_id: &str) -> Vec<serde_json::Value> {
+    ACCESS_LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|line| line["request_id"] == request_id)
+        .cloned()
+        .collect()
+}